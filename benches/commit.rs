@@ -0,0 +1,154 @@
+use banderwagon::Fr;
+use criterion::{criterion_group, BenchmarkId, Criterion};
+use ffi_interface::{update_commitment_sparse, Context};
+use ipa_multipoint::committer::{Committer, DefaultCommitter};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, Instant};
+use verkle_tree_example::codec::fr_to_le_bytes;
+use verkle_trie::constants::CRS;
+
+const NON_ZERO: [usize; 9] = [1, 2, 4, 8, 16, 32, 64, 128, 256];
+
+fn random_scalars(n: usize, rng: &mut StdRng) -> Vec<Fr> {
+    (0..n).map(|_| Fr::from(rng.gen::<u128>())).collect()
+}
+
+// The first `k` indices hold random values, the remaining ones are zero
+struct Input {
+    evaluations: Vec<Fr>,
+    sparse: Vec<(Fr, usize)>,
+    indices: Vec<usize>,
+    old_bytes: Vec<[u8; 32]>,
+    new_bytes: Vec<[u8; 32]>,
+    old_commitment: [u8; 64],
+}
+
+fn input(committer: &DefaultCommitter, k: usize, rng: &mut StdRng) -> Input {
+    let old = random_scalars(256, rng);
+    let new = random_scalars(k, rng);
+
+    let mut evaluations = vec![Fr::from(0u32); 256];
+    evaluations[..k].copy_from_slice(&new);
+
+    Input {
+        sparse: new.iter().copied().zip(0..k).collect(),
+        indices: (0..k).collect(),
+        old_bytes: old[..k].iter().map(fr_to_le_bytes).collect(),
+        new_bytes: new.iter().map(fr_to_le_bytes).collect(),
+        old_commitment: committer.commit_lagrange(&old).to_bytes_uncompressed(),
+        evaluations,
+    }
+}
+
+fn bench_commit(c: &mut Criterion) {
+    let committer = DefaultCommitter::new(&CRS.G);
+    let context = Context::default();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let mut group = c.benchmark_group("commit");
+    for k in NON_ZERO {
+        let input = input(&committer, k, &mut rng);
+
+        group.bench_with_input(BenchmarkId::new("commit_lagrange", k), &input, |b, i| {
+            b.iter(|| committer.commit_lagrange(&i.evaluations))
+        });
+        group.bench_with_input(BenchmarkId::new("commit_sparse", k), &input, |b, i| {
+            b.iter(|| committer.commit_sparse(i.sparse.clone()))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("update_commitment_sparse", k),
+            &input,
+            |b, i| {
+                b.iter(|| {
+                    update_commitment_sparse(
+                        &context,
+                        i.old_commitment,
+                        i.indices.clone(),
+                        i.old_bytes.clone(),
+                        i.new_bytes.clone(),
+                    )
+                    .unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn time<T>(iterations: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(f());
+    }
+    start.elapsed() / iterations
+}
+
+// The crossover report takes minutes, so it only runs when this is set
+const CROSSOVER_ENV: &str = "COMMIT_CROSSOVER";
+// Each timing is the fastest of this many 20 iteration averages
+const SAMPLES: usize = 5;
+// A primitive has lost at k once it is slower for k and the WINDOW - 1 values after it
+const WINDOW: usize = 8;
+
+fn best_of<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..SAMPLES).map(|_| time(20, &mut f)).min().unwrap()
+}
+
+// `wins[k - 1]` says whether the primitive beat commit_lagrange at k
+fn first_consistent_loss(wins: &[bool]) -> Option<usize> {
+    (0..wins.len())
+        .find(|&i| wins[i..(i + WINDOW).min(wins.len())].iter().all(|win| !win))
+        .map(|i| i + 1)
+}
+
+// Criterion reports each primitive on its own, this prints for every k which one
+// wins and the first k from which a sparse update keeps losing to a full recommit
+fn report_crossover() {
+    let committer = DefaultCommitter::new(&CRS.G);
+    let context = Context::default();
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let mut sparse_wins = Vec::new();
+    let mut update_wins = Vec::new();
+    println!("k\tcommit_lagrange\tcommit_sparse\tupdate_commitment_sparse");
+    for k in 1..=256 {
+        let i = input(&committer, k, &mut rng);
+        let full = best_of(|| committer.commit_lagrange(&i.evaluations));
+        let sparse = best_of(|| committer.commit_sparse(i.sparse.clone()));
+        let update = best_of(|| {
+            update_commitment_sparse(
+                &context,
+                i.old_commitment,
+                i.indices.clone(),
+                i.old_bytes.clone(),
+                i.new_bytes.clone(),
+            )
+            .unwrap()
+        });
+        println!("{}\t{:?}\t{:?}\t{:?}", k, full, sparse, update);
+
+        sparse_wins.push(sparse < full);
+        update_wins.push(update < full);
+    }
+
+    println!(
+        "commit_sparse loses to commit_lagrange from {:?} non-zero indices on",
+        first_consistent_loss(&sparse_wins)
+    );
+    println!(
+        "update_commitment_sparse loses to commit_lagrange from {:?} changed indices on",
+        first_consistent_loss(&update_wins)
+    );
+}
+
+criterion_group!(benches, bench_commit);
+
+fn main() {
+    benches();
+    criterion::Criterion::default()
+        .configure_from_args()
+        .final_summary();
+    if std::env::var_os(CROSSOVER_ENV).is_some() {
+        report_crossover();
+    }
+}
@@ -0,0 +1,131 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Bencher, BenchmarkId, Criterion};
+use ipa_multipoint::committer::DefaultCommitter;
+use verkle_tree_example::workload::{Distribution, Workload};
+use verkle_trie::{
+    database::{memory_db::MemoryDb, ReadOnlyHigherDb},
+    proof::prover,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+const SIZES: [usize; 4] = [1, 100, 10_000, 1_000_000];
+
+fn random_keys(n: usize, seed: u64) -> Vec<[u8; 32]> {
+    Workload::new(seed, Distribution::Uniform).keys(n)
+}
+
+// Each iteration gets its own copy of the trie built in setup, so every insert is
+// measured against a trie of the nominal size and leaves nothing behind.
+// `prepare` runs outside of the measurement and returns the key to insert.
+fn bench_insert(
+    b: &mut Bencher,
+    trie: &Trie<MemoryDb, DefaultCommitter>,
+    keys: &mut Workload,
+    prepare: impl Fn(&mut Trie<MemoryDb, DefaultCommitter>, [u8; 32]) -> [u8; 32],
+) {
+    b.iter_batched(
+        || {
+            let mut trie = trie.clone();
+            let key = prepare(&mut trie, keys.next_key());
+            (trie, key)
+        },
+        |(mut trie, key)| {
+            trie.insert_single(key, key);
+            // Dropped outside of the measurement
+            trie
+        },
+        BatchSize::LargeInput,
+    )
+}
+
+fn build_trie(keys: &[[u8; 32]]) -> Trie<MemoryDb, DefaultCommitter> {
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    trie.insert(keys.iter().map(|key| (*key, *key)));
+    trie
+}
+
+fn bench_trie(c: &mut Criterion) {
+    let mut group = c.benchmark_group("trie");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let keys = random_keys(size, 0);
+        let trie = build_trie(&keys);
+        let root = trie.storage.get_branch_meta(&[]).unwrap().commitment;
+
+        group.bench_with_input(BenchmarkId::new("get", size), &keys, |b, keys| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % keys.len();
+                trie.get(keys[i])
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("root_hash", size), &size, |b, _| {
+            b.iter(|| trie.root_hash())
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("create_verkle_proof", size),
+            &keys,
+            |b, keys| {
+                let mut i = 0;
+                b.iter(|| {
+                    i = (i + 1) % keys.len();
+                    prover::create_verkle_proof(&trie.storage, vec![keys[i]]).unwrap()
+                })
+            },
+        );
+
+        let proof = prover::create_verkle_proof(&trie.storage, vec![keys[0]]).unwrap();
+        group.bench_with_input(BenchmarkId::new("check", size), &keys, |b, keys| {
+            b.iter_batched(
+                || proof.clone(),
+                |proof| proof.check(vec![keys[0]], vec![Some(keys[0])], root),
+                BatchSize::SmallInput,
+            )
+        });
+
+        // A stem which is very unlikely to exist yet
+        let mut fresh_keys = Workload::new(1, Distribution::Uniform);
+        group.bench_with_input(
+            BenchmarkId::new("insert_single/fresh_stem", size),
+            &size,
+            |b, _| bench_insert(b, &trie, &mut fresh_keys, |_, key| key),
+        );
+
+        // A new leaf under an existing stem, only C1/C2 and the path above change.
+        // The stem is inserted in setup, so the trie holds one more stem than its size.
+        let mut bases = Workload::new(2, Distribution::Uniform);
+        group.bench_with_input(
+            BenchmarkId::new("insert_single/existing_stem", size),
+            &size,
+            |b, _| {
+                bench_insert(b, &trie, &mut bases, |trie, mut key| {
+                    trie.insert_single(key, key);
+                    key[31] ^= 1;
+                    key
+                })
+            },
+        );
+
+        // Shares 30 bytes with a stem inserted in setup, forcing the longest branch chain
+        let mut bases = Workload::new(3, Distribution::Uniform);
+        group.bench_with_input(
+            BenchmarkId::new("insert_single/deep_path", size),
+            &size,
+            |b, _| {
+                bench_insert(b, &trie, &mut bases, |trie, mut key| {
+                    trie.insert_single(key, key);
+                    key[30] ^= 1;
+                    key
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_trie);
+criterion_main!(benches);
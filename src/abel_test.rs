@@ -3,7 +3,9 @@ use ark_serialize::CanonicalSerialize;
 use banderwagon::{Element, Fr, PrimeField};
 use ffi_interface::{fr_from_le_bytes, update_commitment_sparse, Context, ZERO_POINT};
 use ipa_multipoint::committer::Committer;
-use rand::Rng;
+
+use crate::canonical::{canonicalize_uncompressed, uncompressed_eq};
+use crate::workload::{Distribution, Workload};
 
 const FR_SIZE: usize = 32;
 
@@ -12,8 +14,8 @@ pub fn check_update_bytes() {
     println!("a: {}", a);
 
     let zero_bytes = vec![[0 as u8; FR_SIZE]; 100];
-    let old_bytes = get_random_kvs(100);
-    let new_bytes = get_random_kvs(100);
+    let old_bytes = get_random_kvs(100, 0);
+    let new_bytes = get_random_kvs(100, 1);
 
     let idx: Vec<usize> = (0..100).into_iter().collect();
 
@@ -63,7 +65,13 @@ pub fn check_update_bytes() {
     assert_eq!(new_c_with_delta_element, new_c_element, "element mismatch");
     println!("{:?}\n{:?}", new_c_with_delta_element, new_c_element);
 
-    assert_eq!(new_c_with_delta, new_c, "bytes mismatch");
+    // The raw bytes differ (see the note below), the canonical bytes must not
+    assert!(uncompressed_eq(new_c_with_delta, new_c), "bytes mismatch");
+    assert_eq!(
+        canonicalize_uncompressed(new_c_with_delta),
+        canonicalize_uncompressed(new_c),
+        "canonical bytes mismatch"
+    );
 
     // 结果显示，同一个点，element 的表示不同，但是他们是相等的，他们的 uncompressed bytes 是不相等的.
     // a: 253
@@ -80,13 +88,13 @@ pub fn check_update_bytes() {
     println!("scalar_wnaf: {:?}", scalar_wnaf);
 }
 
-pub fn get_random_kvs(l: usize) -> Vec<[u8; FR_SIZE]> {
-    let mut kvs = vec![];
-    let mut rng = rand::thread_rng();
-    (0..l).into_iter().for_each(|_| {
-        let mut k: [u8; FR_SIZE] = rng.gen();
-        k[31] = 0;
-        kvs.push(k);
-    });
-    kvs
+pub fn get_random_kvs(l: usize, seed: u64) -> Vec<[u8; FR_SIZE]> {
+    let mut workload = Workload::new(seed, Distribution::Uniform);
+    (0..l)
+        .map(|_| {
+            let mut k = workload.next_key();
+            k[31] = 0;
+            k
+        })
+        .collect()
 }
@@ -0,0 +1,421 @@
+use banderwagon::{trait_defs::*, Element, Fr};
+use ipa_multipoint::committer::Committer;
+use rand::{rngs::StdRng, Rng};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::leaf::{split_value, LeafValue};
+use crate::workload::scenario_rng;
+
+pub(crate) type StemWrites = BTreeMap<[u8; 31], BTreeMap<u8, [u8; 32]>>;
+
+// Inserts all key/values and returns the new root hash.
+//
+// Writes are grouped by stem, so every stem gets its C1/C2 and stem commitment updated once,
+// and every branch on the way up is updated once with the deltas of all its changed children.
+// The result is the same as calling `insert_single` for every pair in order.
+pub fn batch_insert<DB, C>(
+    trie: &mut Trie<DB, C>,
+    kvs: impl IntoIterator<Item = ([u8; 32], [u8; 32])>,
+) -> Fr
+where
+    DB: ReadOnlyHigherDb + WriteOnlyHigherDb,
+    C: Committer,
+{
+    let writes = group_by_stem(kvs);
+    let stem_metas = writes
+        .iter()
+        .map(|(stem, leaves)| {
+            (
+                *stem,
+                updated_stem_meta(&trie.storage, &trie.committer, stem, leaves),
+            )
+        })
+        .collect();
+
+    let stems: Vec<[u8; 31]> = writes.keys().copied().collect();
+    let update = BranchUpdate {
+        storage: &trie.storage,
+        committer: &trie.committer,
+        stem_metas,
+    };
+    let mut node_writes = Vec::new();
+    let root = update.update_branch(vec![], &stems, &mut node_writes);
+
+    write_leaves(&mut trie.storage, &writes, &node_writes);
+    apply_writes(&mut trie.storage, node_writes);
+    root.hash_commitment
+}
+
+// Last write wins
+pub(crate) fn group_by_stem(kvs: impl IntoIterator<Item = ([u8; 32], [u8; 32])>) -> StemWrites {
+    let mut writes: StemWrites = BTreeMap::new();
+    for (key, value) in kvs {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        writes.entry(stem).or_default().insert(key[31], value);
+    }
+    writes
+}
+
+// Every stem in `writes` is placed by one of `node_writes`, which gives the depth of its leaves
+pub(crate) fn write_leaves<DB: WriteOnlyHigherDb>(
+    storage: &mut DB,
+    writes: &StemWrites,
+    node_writes: &[NodeWrite],
+) {
+    let depths: HashMap<[u8; 31], u8> = node_writes
+        .iter()
+        .filter_map(|write| match write {
+            NodeWrite::Stem(stem, _, depth) => Some((*stem, *depth)),
+            _ => None,
+        })
+        .collect();
+
+    for (stem, leaves) in writes {
+        for (suffix, value) in leaves {
+            let mut key = [0u8; 32];
+            key[..31].copy_from_slice(stem);
+            key[31] = *suffix;
+            storage.insert_leaf(key, *value, depths[stem]);
+        }
+    }
+}
+
+// Node writes are collected while the new commitments are computed against the
+// old state of the database, and only applied once everything has been computed.
+//
+// The depth follows `Trie::insert_single`: leaves, stems and stem children get the
+// path length of the branch the stem hangs off, branches their own path length.
+#[derive(Debug, Clone)]
+pub(crate) enum NodeWrite {
+    Stem([u8; 31], StemMeta, u8),
+    StemChild(Vec<u8>, [u8; 31], u8),
+    Branch(Vec<u8>, BranchMeta, u8),
+}
+
+pub(crate) fn apply_writes<DB: WriteOnlyHigherDb>(storage: &mut DB, writes: Vec<NodeWrite>) {
+    for write in writes {
+        match write {
+            NodeWrite::Stem(stem, meta, depth) => {
+                storage.insert_stem(stem, meta, depth);
+            }
+            NodeWrite::StemChild(path, stem, depth) => {
+                storage.add_stem_as_branch_child(path, stem, depth);
+            }
+            NodeWrite::Branch(path, meta, depth) => {
+                storage.insert_branch(path, meta, depth);
+            }
+        }
+    }
+}
+
+// C1/C2 and the stem commitment after applying `leaves`, computed from the
+// stored meta and deltas if the stem exists, or from scratch if it does not
+pub(crate) fn updated_stem_meta<DB: ReadOnlyHigherDb, C: Committer>(
+    storage: &DB,
+    committer: &C,
+    stem: &[u8; 31],
+    leaves: &BTreeMap<u8, [u8; 32]>,
+) -> StemMeta {
+    let existing = storage.get_stem_meta(*stem);
+
+    let mut c_1_deltas = Vec::new();
+    let mut c_2_deltas = Vec::new();
+    for (suffix, value) in leaves {
+        let mut key = [0u8; 32];
+        key[..31].copy_from_slice(stem);
+        key[31] = *suffix;
+
+        let old_value = existing.and_then(|_| storage.get_leaf(key));
+        let (old_low, old_high) = LeafValue::from(old_value).to_scalars();
+        let (new_low, new_high) = split_value(value);
+
+        let (deltas, offset) = if *suffix < 128 {
+            (&mut c_1_deltas, 2 * *suffix as usize)
+        } else {
+            (&mut c_2_deltas, 2 * (*suffix as usize - 128))
+        };
+        deltas.push((new_low - old_low, offset));
+        deltas.push((new_high - old_high, offset + 1));
+    }
+
+    let commit = |deltas: Vec<(Fr, usize)>| {
+        if deltas.is_empty() {
+            Element::zero()
+        } else {
+            committer.commit_sparse(deltas)
+        }
+    };
+
+    let (old_c_1, old_c_2) = existing.map_or((Element::zero(), Element::zero()), |meta| {
+        (meta.c_1, meta.c_2)
+    });
+    let c_1 = old_c_1 + commit(c_1_deltas);
+    let c_2 = old_c_2 + commit(c_2_deltas);
+
+    match existing {
+        Some(meta) => {
+            let hash_c1 = group_to_field(&c_1);
+            let hash_c2 = group_to_field(&c_2);
+            let stem_commitment = meta.stem_commitment
+                + commit(vec![
+                    (hash_c1 - meta.hash_c1, 2),
+                    (hash_c2 - meta.hash_c2, 3),
+                ]);
+            StemMeta {
+                c_1,
+                hash_c1,
+                c_2,
+                hash_c2,
+                stem_commitment,
+                hash_stem_commitment: group_to_field(&stem_commitment),
+            }
+        }
+        None => stem_meta_from_c(committer, stem, c_1, c_2),
+    }
+}
+
+// The meta of a new stem that holds exactly `leaves`
+pub(crate) fn fresh_stem_meta<C: Committer>(
+    committer: &C,
+    stem: &[u8; 31],
+    leaves: &BTreeMap<u8, [u8; 32]>,
+) -> StemMeta {
+    let mut c_1_values = Vec::new();
+    let mut c_2_values = Vec::new();
+    for (suffix, value) in leaves {
+        let (low, high) = split_value(value);
+        let (values, offset) = if *suffix < 128 {
+            (&mut c_1_values, 2 * *suffix as usize)
+        } else {
+            (&mut c_2_values, 2 * (*suffix as usize - 128))
+        };
+        values.push((low, offset));
+        values.push((high, offset + 1));
+    }
+
+    let commit = |values: Vec<(Fr, usize)>| {
+        if values.is_empty() {
+            Element::zero()
+        } else {
+            committer.commit_sparse(values)
+        }
+    };
+    stem_meta_from_c(committer, stem, commit(c_1_values), commit(c_2_values))
+}
+
+fn stem_meta_from_c<C: Committer>(
+    committer: &C,
+    stem: &[u8; 31],
+    c_1: Element,
+    c_2: Element,
+) -> StemMeta {
+    let hash_c1 = group_to_field(&c_1);
+    let hash_c2 = group_to_field(&c_2);
+
+    // 1 * G_0 + stem * G_1 + hash(C1) * G_2 + hash(C2) * G_3
+    let stem_commitment = committer.commit_sparse(vec![
+        (Fr::one(), 0),
+        (Fr::from_le_bytes_mod_order(stem), 1),
+        (hash_c1, 2),
+        (hash_c2, 3),
+    ]);
+
+    StemMeta {
+        c_1,
+        hash_c1,
+        c_2,
+        hash_c2,
+        stem_commitment,
+        hash_stem_commitment: group_to_field(&stem_commitment),
+    }
+}
+
+pub(crate) struct BranchUpdate<'a, DB, C> {
+    pub(crate) storage: &'a DB,
+    pub(crate) committer: &'a C,
+    pub(crate) stem_metas: HashMap<[u8; 31], StemMeta>,
+}
+
+impl<'a, DB, C> BranchUpdate<'a, DB, C>
+where
+    DB: ReadOnlyHigherDb,
+    C: Committer,
+{
+    // Stems that are pushed down by a new stem are not part of the batch,
+    // their meta is unchanged
+    fn stem_meta(&self, stem: &[u8; 31]) -> StemMeta {
+        match self.stem_metas.get(stem) {
+            Some(meta) => *meta,
+            None => self.storage.get_stem_meta(*stem).unwrap(),
+        }
+    }
+
+    // `path` is the stem's own path, one longer than the branch it hangs off
+    fn place_stem(&self, path: Vec<u8>, stem: [u8; 31], writes: &mut Vec<NodeWrite>) -> Fr {
+        let depth = (path.len() - 1) as u8;
+        let meta = self.stem_meta(&stem);
+        writes.push(NodeWrite::Stem(stem, meta, depth));
+        writes.push(NodeWrite::StemChild(path, stem, depth));
+        meta.hash_stem_commitment
+    }
+
+    // `stems` are sorted and all of them live under `path`.
+    // Each child index is visited once and the branch commitment is updated
+    // with one sparse commitment over all of the changed children.
+    pub(crate) fn update_branch(
+        &self,
+        path: Vec<u8>,
+        stems: &[[u8; 31]],
+        writes: &mut Vec<NodeWrite>,
+    ) -> BranchMeta {
+        let deltas = stems
+            .chunk_by(|a, b| a[path.len()] == b[path.len()])
+            .map(|group| self.update_child(&path, group, writes))
+            .collect();
+        self.finish_branch(path, deltas, writes)
+    }
+
+    // Updates the child of `path` that holds `group`, and returns the change of its hash
+    // along with its index
+    pub(crate) fn update_child(
+        &self,
+        path: &[u8],
+        group: &[[u8; 31]],
+        writes: &mut Vec<NodeWrite>,
+    ) -> (Fr, usize) {
+        let index = group[0][path.len()];
+        let mut child_path = path.to_vec();
+        child_path.push(index);
+
+        let (old_hash, new_hash) = match self.storage.get_branch_child(path, index) {
+            None if group.len() == 1 => (Fr::zero(), self.place_stem(child_path, group[0], writes)),
+            None => (
+                Fr::zero(),
+                self.update_branch(child_path, group, writes)
+                    .hash_commitment,
+            ),
+            Some(BranchChild::Stem(existing)) => {
+                let old_hash = self
+                    .storage
+                    .get_stem_meta(existing)
+                    .unwrap()
+                    .hash_stem_commitment;
+                if group == [existing] {
+                    (old_hash, self.place_stem(child_path, existing, writes))
+                } else {
+                    // The existing stem and the new ones need a branch to tell them apart
+                    let mut merged = group.to_vec();
+                    if let Err(position) = merged.binary_search(&existing) {
+                        merged.insert(position, existing);
+                    }
+                    (
+                        old_hash,
+                        self.update_branch(child_path, &merged, writes)
+                            .hash_commitment,
+                    )
+                }
+            }
+            Some(BranchChild::Branch(meta)) => (
+                meta.hash_commitment,
+                self.update_branch(child_path, group, writes)
+                    .hash_commitment,
+            ),
+        };
+        (new_hash - old_hash, index as usize)
+    }
+
+    pub(crate) fn finish_branch(
+        &self,
+        path: Vec<u8>,
+        deltas: Vec<(Fr, usize)>,
+        writes: &mut Vec<NodeWrite>,
+    ) -> BranchMeta {
+        // A branch that does not exist yet starts out as the zero commitment
+        let old_meta = self
+            .storage
+            .get_branch_meta(&path)
+            .unwrap_or_else(BranchMeta::zero);
+
+        let commitment = old_meta.commitment + self.committer.commit_sparse(deltas);
+        let meta = BranchMeta {
+            commitment,
+            hash_commitment: group_to_field(&commitment),
+        };
+        let depth = path.len() as u8;
+        writes.push(NodeWrite::Branch(path, meta, depth));
+        meta
+    }
+}
+
+fn random_block(rng: &mut StdRng, existing: &[[u8; 32]], n: usize) -> Vec<([u8; 32], [u8; 32])> {
+    (0..n)
+        .map(|i| {
+            let mut key: [u8; 32] = rng.gen();
+            match i % 4 {
+                // Overwrite an existing leaf
+                0 if !existing.is_empty() => key = existing[rng.gen_range(0..existing.len())],
+                // New leaf in an existing stem
+                1 if !existing.is_empty() => {
+                    key[..31].copy_from_slice(&existing[rng.gen_range(0..existing.len())][..31])
+                }
+                // New stem sharing a long prefix with an existing one
+                2 if !existing.is_empty() => {
+                    let shared = rng.gen_range(1..31);
+                    key[..shared]
+                        .copy_from_slice(&existing[rng.gen_range(0..existing.len())][..shared])
+                }
+                _ => {}
+            }
+            (key, rng.gen())
+        })
+        .collect()
+}
+
+pub fn batch_matches_sequential() {
+    let mut sequential = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let mut batched = sequential.clone();
+    let mut keys: Vec<[u8; 32]> = Vec::new();
+    let mut rng = scenario_rng("batch_matches_sequential");
+
+    for block in 0..5 {
+        let kvs = random_block(&mut rng, &keys, 500);
+
+        let start = Instant::now();
+        for (key, value) in &kvs {
+            sequential.insert_single(*key, *value);
+        }
+        let sequential_time = start.elapsed();
+
+        let start = Instant::now();
+        let root = batch_insert(&mut batched, kvs.iter().copied());
+        let batch_time = start.elapsed();
+
+        assert_eq!(root, sequential.root_hash());
+        assert_eq!(batched.root_commitment(), sequential.root_commitment());
+        for (key, _) in &kvs {
+            assert_eq!(batched.get(*key), sequential.get(*key));
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            assert_eq!(
+                batched.storage.get_stem_meta(stem),
+                sequential.storage.get_stem_meta(stem)
+            );
+        }
+
+        println!(
+            "block {}: sequential {:?}, batch {:?}",
+            block, sequential_time, batch_time
+        );
+        keys.extend(kvs.into_iter().map(|(key, _)| key));
+    }
+}
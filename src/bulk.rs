@@ -0,0 +1,381 @@
+use banderwagon::Fr;
+use ipa_multipoint::committer::Committer;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::time::Instant;
+use verkle_trie::{
+    database::{memory_db::MemoryDb, meta::BranchMeta, WriteOnlyHigherDb},
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::batch::{apply_writes, fresh_stem_meta, NodeWrite};
+use crate::codec::{parse_hex, CodecError};
+use crate::file_db::FileDb;
+use crate::workload::scenario_rng;
+
+#[derive(Debug)]
+pub enum BulkError {
+    Io(io::Error),
+    Codec { line: usize, err: CodecError },
+    MalformedLine(usize),
+    // The binary input ended partway through the record starting at `offset`
+    TruncatedRecord { offset: u64, len: usize },
+    // Keys have to be strictly increasing
+    Unsorted { previous: [u8; 32], key: [u8; 32] },
+}
+
+impl fmt::Display for BulkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BulkError::Io(err) => write!(f, "io error: {}", err),
+            BulkError::Codec { line, err } => write!(f, "line {}: {}", line, err),
+            BulkError::MalformedLine(line) => {
+                write!(f, "line {}: expected `key,value` in hex", line)
+            }
+            BulkError::TruncatedRecord { offset, len } => {
+                write!(f, "record at byte {} has {} of 64 bytes", offset, len)
+            }
+            BulkError::Unsorted { previous, key } => write!(
+                f,
+                "key {} does not come after {}",
+                hex::encode(key),
+                hex::encode(previous)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BulkError {}
+
+impl From<io::Error> for BulkError {
+    fn from(err: io::Error) -> Self {
+        BulkError::Io(err)
+    }
+}
+
+pub type KeyValue = ([u8; 32], [u8; 32]);
+
+// One `key,value` pair per line, both 32 bytes of hex with an optional 0x prefix.
+// Empty lines and lines starting with # are skipped.
+pub fn read_csv(reader: impl BufRead) -> impl Iterator<Item = Result<KeyValue, BulkError>> {
+    reader.lines().enumerate().filter_map(|(i, line)| {
+        let line_number = i + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => return Some(Err(BulkError::Io(err))),
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let parse = |s: &str| {
+            parse_hex::<32>(s.trim()).map_err(|err| BulkError::Codec {
+                line: line_number,
+                err,
+            })
+        };
+        Some(match line.split_once(',') {
+            Some((key, value)) => parse(key).and_then(|key| Ok((key, parse(value)?))),
+            None => Err(BulkError::MalformedLine(line_number)),
+        })
+    })
+}
+
+// Back to back 64 byte records, the key followed by the value. The input may
+// only end on a record boundary, a partial last record is an error.
+pub fn read_binary(reader: impl Read) -> impl Iterator<Item = Result<KeyValue, BulkError>> {
+    let mut reader = BufReader::new(reader);
+    let mut offset = 0u64;
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let mut record = [0u8; 64];
+        let mut len = 0;
+        while len < record.len() {
+            match reader.read(&mut record[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    done = true;
+                    return Some(Err(BulkError::Io(err)));
+                }
+            }
+        }
+
+        match len {
+            0 => {
+                done = true;
+                None
+            }
+            64 => {
+                offset += 64;
+                Some(Ok((
+                    record[..32].try_into().unwrap(),
+                    record[32..].try_into().unwrap(),
+                )))
+            }
+            len => {
+                done = true;
+                Some(Err(BulkError::TruncatedRecord { offset, len }))
+            }
+        }
+    })
+}
+
+pub fn write_csv(writer: impl Write, kvs: &[KeyValue]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for (key, value) in kvs {
+        writeln!(writer, "{},{}", hex::encode(key), hex::encode(value))?;
+    }
+    writer.flush()
+}
+
+pub fn write_binary(writer: impl Write, kvs: &[KeyValue]) -> io::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for (key, value) in kvs {
+        writer.write_all(key)?;
+        writer.write_all(value)?;
+    }
+    writer.flush()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadStats {
+    pub keys: usize,
+    pub stems: usize,
+    pub root: Fr,
+}
+
+// Builds a trie into an empty `storage` from key/values sorted by key.
+//
+// The whole input is read and checked before anything is written, so `storage` is
+// left untouched when an error is returned. Until then the key/values are held in
+// memory, 64 bytes per key.
+//
+// A stem's leaves and meta are written as soon as the stem after it has been seen,
+// since that fixes its depth, and only the stem's hash is kept for the branches.
+// The branches are then built bottom-up over the sorted stems, each branch commitment
+// being a single commitment over all of its children.
+pub fn bulk_load<DB, C>(
+    storage: &mut DB,
+    committer: &C,
+    kvs: impl IntoIterator<Item = Result<KeyValue, BulkError>>,
+) -> Result<LoadStats, BulkError>
+where
+    DB: WriteOnlyHigherDb,
+    C: Committer,
+{
+    let mut input: Vec<KeyValue> = Vec::new();
+    for kv in kvs {
+        let (key, value) = kv?;
+        if let Some((previous, _)) = input.last() {
+            if key <= *previous {
+                return Err(BulkError::Unsorted {
+                    previous: *previous,
+                    key,
+                });
+            }
+        }
+        input.push((key, value));
+    }
+
+    let groups: Vec<&[KeyValue]> = input.chunk_by(|a, b| a.0[..31] == b.0[..31]).collect();
+    let stem_of = |group: &[KeyValue]| -> [u8; 31] { group[0].0[..31].try_into().unwrap() };
+
+    let mut stems: Vec<([u8; 31], Fr)> = Vec::with_capacity(groups.len());
+    for (i, group) in groups.iter().enumerate() {
+        let stem = stem_of(group);
+        // A stem hangs off the branch at the longest prefix it shares with either neighbour
+        let neighbours = [i.checked_sub(1), Some(i + 1)];
+        let depth = neighbours
+            .into_iter()
+            .flatten()
+            .filter_map(|j| groups.get(j))
+            .map(|other| shared_prefix(&stem, &stem_of(other)))
+            .max()
+            .unwrap_or(0) as u8;
+
+        let leaves: BTreeMap<u8, [u8; 32]> =
+            group.iter().map(|(key, value)| (key[31], *value)).collect();
+        for (key, value) in group.iter() {
+            storage.insert_leaf(*key, *value, depth);
+        }
+        let meta = fresh_stem_meta(committer, &stem, &leaves);
+        storage.insert_stem(stem, meta, depth);
+        stems.push((stem, meta.hash_stem_commitment));
+    }
+
+    let mut writes = Vec::new();
+    let root = build_branch(committer, vec![], &stems, &mut writes);
+    apply_writes(storage, writes);
+
+    Ok(LoadStats {
+        keys: input.len(),
+        stems: stems.len(),
+        root: root.hash_commitment,
+    })
+}
+
+fn shared_prefix(a: &[u8; 31], b: &[u8; 31]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+pub(crate) fn build_branch<C: Committer>(
+    committer: &C,
+    path: Vec<u8>,
+    stems: &[([u8; 31], Fr)],
+    writes: &mut Vec<NodeWrite>,
+) -> BranchMeta {
+    let children = stems
+        .chunk_by(|a, b| a.0[path.len()] == b.0[path.len()])
+        .map(|group| build_child(committer, &path, group, writes))
+        .collect();
+    finish_branch(committer, path, children, writes)
+}
+
+// Builds the child of `path` that holds `group`, and returns its hash along with its index.
+// A stem is only placed, its meta is up to the caller.
+pub(crate) fn build_child<C: Committer>(
+    committer: &C,
+    path: &[u8],
+    group: &[([u8; 31], Fr)],
+    writes: &mut Vec<NodeWrite>,
+) -> (Fr, usize) {
+    let depth = path.len() as u8;
+    let index = group[0].0[path.len()];
+    let mut child_path = path.to_vec();
+    child_path.push(index);
+
+    let hash = match group {
+        [(stem, hash)] => {
+            writes.push(NodeWrite::StemChild(child_path, *stem, depth));
+            *hash
+        }
+        _ => build_branch(committer, child_path, group, writes).hash_commitment,
+    };
+    (hash, index as usize)
+}
+
+pub(crate) fn finish_branch<C: Committer>(
+    committer: &C,
+    path: Vec<u8>,
+    children: Vec<(Fr, usize)>,
+    writes: &mut Vec<NodeWrite>,
+) -> BranchMeta {
+    let meta = if children.is_empty() {
+        BranchMeta::zero()
+    } else {
+        let commitment = committer.commit_sparse(children);
+        BranchMeta {
+            commitment,
+            hash_commitment: group_to_field(&commitment),
+        }
+    };
+    let depth = path.len() as u8;
+    writes.push(NodeWrite::Branch(path, meta, depth));
+    meta
+}
+
+pub fn bulk_load_matches_trie() {
+    let mut rng = scenario_rng("bulk_load_matches_trie");
+    let mut kvs: Vec<KeyValue> = (0..10_000)
+        .map(|i| {
+            let mut key: [u8; 32] = rng.gen();
+            // Some stems with several leaves and some long shared prefixes
+            if i % 5 == 0 {
+                key[..31].copy_from_slice(&[0x5a; 31]);
+            }
+            if i % 11 == 0 {
+                key[..20].copy_from_slice(&[0x77; 20]);
+            }
+            (key, rng.gen())
+        })
+        .collect();
+    kvs.sort();
+    kvs.dedup_by_key(|(key, _)| *key);
+
+    let dir = std::env::temp_dir();
+    let csv_path = dir.join(format!("verkle_bulk_{}.csv", std::process::id()));
+    let bin_path = dir.join(format!("verkle_bulk_{}.bin", std::process::id()));
+    let db_path = dir.join(format!("verkle_bulk_{}.log", std::process::id()));
+    write_csv(File::create(&csv_path).unwrap(), &kvs).unwrap();
+    write_binary(File::create(&bin_path).unwrap(), &kvs).unwrap();
+
+    let mut expected = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let start = Instant::now();
+    expected.insert(kvs.iter().copied());
+    println!("{} sequential inserts: {:?}", kvs.len(), start.elapsed());
+
+    // MemoryDb from csv
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let start = Instant::now();
+    let reader = BufReader::new(File::open(&csv_path).unwrap());
+    let stats = bulk_load(&mut trie.storage, &trie.committer, read_csv(reader)).unwrap();
+    println!("bulk load from csv: {:?} {:?}", stats, start.elapsed());
+    assert_eq!(stats.keys, kvs.len());
+    assert_eq!(stats.root, expected.root_hash());
+    assert_eq!(trie.root_commitment(), expected.root_commitment());
+    for (key, value) in kvs.iter().step_by(97) {
+        assert_eq!(trie.get(*key), Some(*value));
+    }
+
+    // Persistent store from the binary format
+    let _ = std::fs::remove_file(&db_path);
+    let mut trie = Trie::new(DefaultConfig::new(FileDb::open(&db_path).unwrap()));
+    let stats = bulk_load(
+        &mut trie.storage,
+        &trie.committer,
+        read_binary(File::open(&bin_path).unwrap()),
+    )
+    .unwrap();
+    trie.storage.sync().unwrap();
+    assert_eq!(stats.root, expected.root_hash());
+    drop(trie);
+    let reopened = Trie::new(DefaultConfig::new(FileDb::open(&db_path).unwrap()));
+    assert_eq!(reopened.root_hash(), expected.root_hash());
+
+    // A truncated last record is an error, not a shorter input
+    let bytes = std::fs::read(&bin_path).unwrap();
+    let truncated: Vec<_> = read_binary(&bytes[..bytes.len() - 10]).collect();
+    assert_eq!(truncated.len(), kvs.len());
+    assert!(truncated[..kvs.len() - 1].iter().all(Result::is_ok));
+    assert!(matches!(
+        truncated.last(),
+        Some(Err(BulkError::TruncatedRecord { len: 54, .. }))
+    ));
+    assert_eq!(read_binary(&bytes[..]).count(), kvs.len());
+
+    // Unsorted input is rejected, and nothing that came before it is written
+    let mut unsorted: Vec<_> = kvs[..100].iter().copied().map(Ok).collect();
+    unsorted.push(Ok(kvs[0]));
+    let mut db = MemoryDb::new();
+    assert!(matches!(
+        bulk_load(&mut db, &expected.committer, unsorted),
+        Err(BulkError::Unsorted { .. })
+    ));
+    assert!(db.leaf_table.is_empty() && db.stem_table.is_empty() && db.branch_table.is_empty());
+
+    // The same goes for a truncated record at the end of the input
+    let mut db = MemoryDb::new();
+    assert!(matches!(
+        bulk_load(
+            &mut db,
+            &expected.committer,
+            read_binary(&bytes[..bytes.len() - 10])
+        ),
+        Err(BulkError::TruncatedRecord { .. })
+    ));
+    assert!(db.leaf_table.is_empty() && db.stem_table.is_empty());
+
+    for path in [csv_path, bin_path, db_path] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
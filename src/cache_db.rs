@@ -0,0 +1,208 @@
+use lru::LruCache;
+use std::cell::{Cell, RefCell};
+use std::num::NonZeroUsize;
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    proof::prover,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub branch_hits: u64,
+    pub branch_misses: u64,
+    pub stem_hits: u64,
+    pub stem_misses: u64,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let hits = self.branch_hits + self.stem_hits;
+        let total = hits + self.branch_misses + self.stem_misses;
+        if total == 0 {
+            return 0.0;
+        }
+        hits as f64 / total as f64
+    }
+}
+
+// Keeps the most recently used branch children and stem metas of the wrapped
+// database in memory. Absent entries are cached too, since proofs of absence
+// keep asking for the same empty slots.
+//
+// Writes go through to the wrapped database and refresh the cache, so it never
+// serves stale entries.
+pub struct CacheDb<DB> {
+    inner: DB,
+    branches: RefCell<LruCache<Vec<u8>, Option<BranchChild>>>,
+    stems: RefCell<LruCache<[u8; 31], Option<StemMeta>>>,
+    stats: Cell<CacheStats>,
+}
+
+impl<DB> CacheDb<DB> {
+    pub fn new(inner: DB, branch_capacity: usize, stem_capacity: usize) -> CacheDb<DB> {
+        CacheDb {
+            inner,
+            branches: RefCell::new(LruCache::new(
+                NonZeroUsize::new(branch_capacity).expect("branch capacity must be non-zero"),
+            )),
+            stems: RefCell::new(LruCache::new(
+                NonZeroUsize::new(stem_capacity).expect("stem capacity must be non-zero"),
+            )),
+            stats: Cell::new(CacheStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.set(CacheStats::default());
+    }
+
+    pub fn inner(&self) -> &DB {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.inner
+    }
+
+    fn record(&self, f: impl FnOnce(&mut CacheStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+}
+
+impl<DB: ReadOnlyHigherDb> CacheDb<DB> {
+    fn cached_branch_child(&self, path: &[u8]) -> Option<BranchChild> {
+        if let Some(child) = self.branches.borrow_mut().get(path) {
+            self.record(|s| s.branch_hits += 1);
+            return child.clone();
+        }
+        self.record(|s| s.branch_misses += 1);
+
+        let child = match path.split_last() {
+            Some((index, parent)) => self.inner.get_branch_child(parent, *index),
+            // The root is always a branch
+            None => self.inner.get_branch_meta(path).map(BranchChild::Branch),
+        };
+        self.branches.borrow_mut().put(path.to_vec(), child.clone());
+        child
+    }
+}
+
+impl<DB: ReadOnlyHigherDb> ReadOnlyHigherDb for CacheDb<DB> {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        if let Some(meta) = self.stems.borrow_mut().get(&stem_key) {
+            self.record(|s| s.stem_hits += 1);
+            return *meta;
+        }
+        self.record(|s| s.stem_misses += 1);
+
+        let meta = self.inner.get_stem_meta(stem_key);
+        self.stems.borrow_mut().put(stem_key, meta);
+        meta
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.inner.get_branch_children(branch_id)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        match self.cached_branch_child(key) {
+            Some(BranchChild::Branch(meta)) => Some(meta),
+            _ => None,
+        }
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        let mut path = branch_id.to_vec();
+        path.push(index);
+        self.cached_branch_child(&path)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.inner.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.inner.get_leaf(key)
+    }
+}
+
+impl<DB: WriteOnlyHigherDb> WriteOnlyHigherDb for CacheDb<DB> {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        self.inner.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        self.stems.get_mut().put(key, Some(meta));
+        self.inner.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        self.branches
+            .get_mut()
+            .put(branch_child_id.clone(), Some(BranchChild::Stem(stem_id)));
+        self.inner
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        self.branches
+            .get_mut()
+            .put(key.clone(), Some(BranchChild::Branch(meta)));
+        self.inner.insert_branch(key, meta, depth)
+    }
+}
+
+pub fn cached_repeated_proofs() {
+    let db = CacheDb::new(MemoryDb::new(), 1024, 1024);
+    let mut trie = Trie::new(DefaultConfig::new(db));
+
+    let mut keys = Vec::new();
+    for i in 0..=255u8 {
+        let mut key = [0u8; 32];
+        key[0] = i;
+        key[1] = i.wrapping_mul(7);
+        keys.push(key);
+        trie.insert_single(key, key);
+    }
+    let root = trie.root_commitment();
+
+    trie.storage.reset_stats();
+    for _ in 0..10 {
+        let proof = prover::create_verkle_proof(&trie.storage, keys[..16].to_vec()).unwrap();
+        let values = keys[..16].iter().map(|key| Some(*key)).collect();
+        let (ok, _) = proof.check(keys[..16].to_vec(), values, root);
+        assert!(ok);
+    }
+
+    let stats = trie.storage.stats();
+    println!("cache stats: {:?}, hit rate {:.2}", stats, stats.hit_rate());
+    // Everything after the first proof is served from the cache
+    assert!(stats.branch_hits > stats.branch_misses);
+    assert!(stats.stem_hits > stats.stem_misses);
+
+    // A write must be visible through the cache
+    trie.insert_single(keys[0], [0xff; 32]);
+    assert_eq!(trie.get(keys[0]), Some([0xff; 32]));
+    let stem: [u8; 31] = keys[0][0..31].try_into().unwrap();
+    assert_eq!(
+        trie.storage.get_stem_meta(stem),
+        trie.storage.inner().get_stem_meta(stem)
+    );
+}
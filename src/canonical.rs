@@ -0,0 +1,50 @@
+use banderwagon::Element;
+
+// Banderwagon identifies (x, y) with (-x, -y), so two equal `Element`s can carry
+// different coordinates and therefore different uncompressed bytes.
+// `update_commitment_sparse` returns whichever representative the addition chain
+// produced, see `abel_test::check_update_bytes`.
+//
+// The canonical representative is the affine point obtained by decompressing the
+// compressed encoding, since the compressed encoding already fixes the sign.
+pub fn canonical_element(element: &Element) -> Element {
+    Element::from_bytes(&element.to_bytes()).expect("compressed encoding of a valid element")
+}
+
+pub fn canonical_uncompressed(element: &Element) -> [u8; 64] {
+    canonical_element(element).to_bytes_uncompressed()
+}
+
+pub fn canonicalize_uncompressed(bytes: [u8; 64]) -> [u8; 64] {
+    canonical_uncompressed(&Element::from_bytes_unchecked_uncompressed(bytes))
+}
+
+// Compares two uncompressed commitments as group elements rather than as bytes
+pub fn uncompressed_eq(a: [u8; 64], b: [u8; 64]) -> bool {
+    a == b || canonicalize_uncompressed(a) == canonicalize_uncompressed(b)
+}
+
+pub fn check_canonical_encoding() {
+    let g = Element::prime_subgroup_generator();
+    let neg_g = -g;
+
+    // g and -g are different points, so they must not collapse to the same encoding
+    assert_ne!(canonical_uncompressed(&g), canonical_uncompressed(&neg_g));
+
+    // g + g computed two ways lands on the same canonical bytes
+    let doubled = g + g;
+    let sum = (g + g + g) - g;
+    assert_eq!(doubled, sum);
+    assert_eq!(
+        canonical_uncompressed(&doubled),
+        canonical_uncompressed(&sum)
+    );
+    assert!(uncompressed_eq(
+        doubled.to_bytes_uncompressed(),
+        sum.to_bytes_uncompressed()
+    ));
+
+    // Canonicalization is idempotent
+    let canonical = canonical_uncompressed(&sum);
+    assert_eq!(canonicalize_uncompressed(canonical), canonical);
+}
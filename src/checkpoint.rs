@@ -0,0 +1,263 @@
+use ipa_multipoint::committer::DefaultCommitter;
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    proof::prover,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+// The value an entry had before the write, None if it did not exist
+#[derive(Debug, Clone)]
+enum Undo {
+    Leaf([u8; 32], Option<[u8; 32]>),
+    Stem([u8; 31], Option<StemMeta>),
+    Branch(Vec<u8>, Option<BranchChild>),
+}
+
+// A `MemoryDb` that journals the previous value of every entry it overwrites
+// while a checkpoint is open. All commitments live in the database, so undoing
+// the journal restores the commitments as well as the leaves.
+#[derive(Debug, Default)]
+pub struct JournalDb {
+    db: MemoryDb,
+    journals: Vec<Vec<Undo>>,
+}
+
+impl JournalDb {
+    pub fn new() -> JournalDb {
+        JournalDb::default()
+    }
+
+    fn record(&mut self, undo: impl FnOnce(&MemoryDb) -> Undo) {
+        if self.journals.is_empty() {
+            return;
+        }
+        let undo = undo(&self.db);
+        self.journals.last_mut().unwrap().push(undo);
+    }
+
+    fn undo(&mut self, undo: Undo) {
+        match undo {
+            Undo::Leaf(key, Some(value)) => {
+                self.db.leaf_table.insert(key, value);
+            }
+            Undo::Leaf(key, None) => {
+                self.db.leaf_table.remove(&key);
+            }
+            Undo::Stem(stem, Some(meta)) => {
+                self.db.stem_table.insert(stem, meta);
+            }
+            Undo::Stem(stem, None) => {
+                self.db.stem_table.remove(&stem);
+            }
+            Undo::Branch(path, Some(child)) => {
+                self.db.branch_table.insert(path, child);
+            }
+            Undo::Branch(path, None) => {
+                self.db.branch_table.remove(&path);
+            }
+        }
+    }
+}
+
+impl ReadOnlyHigherDb for JournalDb {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.db.get_stem_meta(stem_key)
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.db.get_branch_children(branch_id)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.db.get_branch_meta(key)
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        self.db.get_branch_child(branch_id, index)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.db.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.db.get_leaf(key)
+    }
+}
+
+impl WriteOnlyHigherDb for JournalDb {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        self.record(|db| Undo::Leaf(key, db.leaf_table.get(&key).copied()));
+        self.db.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        self.record(|db| Undo::Stem(key, db.stem_table.get(&key).copied()));
+        self.db.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        self.record(|db| {
+            Undo::Branch(
+                branch_child_id.clone(),
+                db.branch_table.get(&branch_child_id).cloned(),
+            )
+        });
+        self.db
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        self.record(|db| Undo::Branch(key.clone(), db.branch_table.get(&key).cloned()));
+        self.db.insert_branch(key, meta, depth)
+    }
+}
+
+// A trie whose writes can be rolled back to an earlier checkpoint.
+// Checkpoints nest: committing one folds its journal into the enclosing checkpoint,
+// so the enclosing checkpoint can still roll those writes back.
+pub struct CheckpointTrie {
+    trie: Trie<JournalDb, DefaultCommitter>,
+}
+
+impl Default for CheckpointTrie {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CheckpointTrie {
+    pub fn new() -> CheckpointTrie {
+        CheckpointTrie {
+            trie: Trie::new(DefaultConfig::new(JournalDb::new())),
+        }
+    }
+
+    pub fn trie(&self) -> &Trie<JournalDb, DefaultCommitter> {
+        &self.trie
+    }
+
+    pub fn insert_single(&mut self, key: [u8; 32], value: [u8; 32]) {
+        self.trie.insert_single(key, value)
+    }
+
+    pub fn get(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.trie.get(key)
+    }
+
+    // The number of open checkpoints
+    pub fn depth(&self) -> usize {
+        self.trie.storage.journals.len()
+    }
+
+    // Opens a checkpoint and returns the depth it was opened at
+    pub fn checkpoint(&mut self) -> usize {
+        self.trie.storage.journals.push(Vec::new());
+        self.depth()
+    }
+
+    // Keeps the writes made since the innermost checkpoint
+    pub fn commit(&mut self) {
+        let journal = self
+            .trie
+            .storage
+            .journals
+            .pop()
+            .expect("no checkpoint to commit");
+        if let Some(parent) = self.trie.storage.journals.last_mut() {
+            parent.extend(journal);
+        }
+    }
+
+    // Undoes the writes made since the innermost checkpoint
+    pub fn revert(&mut self) {
+        let journal = self
+            .trie
+            .storage
+            .journals
+            .pop()
+            .expect("no checkpoint to revert");
+        for undo in journal.into_iter().rev() {
+            self.trie.storage.undo(undo);
+        }
+    }
+}
+
+// The scenario of `trie::simple_insert`, rolling back instead of cloning the trie
+pub fn checkpoint_and_rollback() {
+    let mut trie = CheckpointTrie::new();
+
+    let key1 = [
+        0, 147, 89, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31, 32,
+    ];
+    let key2 = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    let mut key3 = key2;
+    key3[31] = 0xff;
+    let mut key4 = key2;
+    key4[30] = 0xff;
+    let mut key6 = key2;
+    key6[31] = 0x7f;
+
+    for key in [key1, key2, key3, key4] {
+        trie.insert_single(key, key);
+    }
+    let root = trie.trie().root_hash();
+    let stem: [u8; 31] = key2[0..31].try_into().unwrap();
+    let stem_meta = trie.trie().storage.get_stem_meta(stem);
+
+    // A new leaf in an existing stem, and a new stem that pushes key1's stem down
+    trie.checkpoint();
+    trie.insert_single(key6, key6);
+    let mut key7 = key1;
+    key7[2] = 0;
+    trie.insert_single(key7, key7);
+    assert_ne!(trie.trie().root_hash(), root);
+    trie.revert();
+
+    assert_eq!(trie.trie().root_hash(), root);
+    assert_eq!(trie.trie().storage.get_stem_meta(stem), stem_meta);
+    assert_eq!(trie.get(key6), None);
+    assert_eq!(trie.get(key7), None);
+
+    // Nested: the inner checkpoint is reverted, the outer one committed
+    trie.checkpoint();
+    trie.insert_single(key6, key6);
+    let with_key6 = trie.trie().root_hash();
+    trie.checkpoint();
+    trie.insert_single(key1, [0u8; 32]);
+    trie.revert();
+    assert_eq!(trie.trie().root_hash(), with_key6);
+    assert_eq!(trie.get(key1), Some(key1));
+    trie.commit();
+    assert_eq!(trie.depth(), 0);
+    assert_eq!(trie.trie().root_hash(), with_key6);
+
+    // Committing an inner checkpoint still lets the outer one revert its writes
+    trie.checkpoint();
+    trie.checkpoint();
+    trie.insert_single(key3, [0u8; 32]);
+    trie.commit();
+    trie.revert();
+    assert_eq!(trie.trie().root_hash(), with_key6);
+    assert_eq!(trie.get(key3), Some(key3));
+
+    // The rolled back state is still provable
+    let keys = vec![key6, key7];
+    let proof = prover::create_verkle_proof(&trie.trie().storage, keys.clone()).unwrap();
+    let (ok, _) = proof.check(keys, vec![Some(key6), None], trie.trie().root_commitment());
+    assert!(ok);
+}
@@ -0,0 +1,205 @@
+use ark_serialize::CanonicalSerialize;
+use banderwagon::{Element, Fr, PrimeField};
+use std::fmt;
+use verkle_trie::group_to_field;
+
+use crate::canonical::canonical_uncompressed;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    InvalidLength { expected: usize, got: usize },
+    // The bytes encode an integer which is not smaller than the scalar field order
+    NonCanonicalScalar,
+    // The bytes do not encode a point in the banderwagon subgroup
+    InvalidPoint,
+    InvalidHex(hex::FromHexError),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::InvalidLength { expected, got } => {
+                write!(f, "expected {} bytes, got {}", expected, got)
+            }
+            CodecError::NonCanonicalScalar => {
+                write!(f, "scalar is not reduced modulo the field order")
+            }
+            CodecError::InvalidPoint => write!(f, "bytes do not encode a banderwagon element"),
+            CodecError::InvalidHex(err) => write!(f, "invalid hex: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+impl From<hex::FromHexError> for CodecError {
+    fn from(err: hex::FromHexError) -> Self {
+        CodecError::InvalidHex(err)
+    }
+}
+
+fn to_array<const N: usize>(bytes: &[u8]) -> Result<[u8; N], CodecError> {
+    bytes.try_into().map_err(|_| CodecError::InvalidLength {
+        expected: N,
+        got: bytes.len(),
+    })
+}
+
+// Scalars
+
+pub fn fr_to_le_bytes(scalar: &Fr) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    scalar
+        .serialize_compressed(&mut bytes[..])
+        .expect("a scalar always fits in 32 bytes");
+    bytes
+}
+
+pub fn fr_to_be_bytes(scalar: &Fr) -> [u8; 32] {
+    let mut bytes = fr_to_le_bytes(scalar);
+    bytes.reverse();
+    bytes
+}
+
+pub fn fr_from_le_bytes_mod_order(bytes: &[u8]) -> Fr {
+    Fr::from_le_bytes_mod_order(bytes)
+}
+
+pub fn fr_from_be_bytes_mod_order(bytes: &[u8]) -> Fr {
+    Fr::from_be_bytes_mod_order(bytes)
+}
+
+// Rejects anything that is not exactly 32 bytes or not smaller than the field order
+pub fn fr_from_le_bytes_strict(bytes: &[u8]) -> Result<Fr, CodecError> {
+    let bytes: [u8; 32] = to_array(bytes)?;
+    let scalar = Fr::from_le_bytes_mod_order(&bytes);
+    if fr_to_le_bytes(&scalar) != bytes {
+        return Err(CodecError::NonCanonicalScalar);
+    }
+    Ok(scalar)
+}
+
+pub fn fr_from_be_bytes_strict(bytes: &[u8]) -> Result<Fr, CodecError> {
+    let mut bytes: [u8; 32] = to_array(bytes)?;
+    bytes.reverse();
+    fr_from_le_bytes_strict(&bytes)
+}
+
+// Points
+
+pub fn element_to_compressed(element: &Element) -> [u8; 32] {
+    element.to_bytes()
+}
+
+pub fn element_from_compressed(bytes: &[u8]) -> Result<Element, CodecError> {
+    let bytes: [u8; 32] = to_array(bytes)?;
+    Element::from_bytes(&bytes).ok_or(CodecError::InvalidPoint)
+}
+
+// The uncompressed form is the one `ffi_interface` passes around.
+// It is not unique, see `canonical`.
+pub fn element_to_uncompressed(element: &Element) -> [u8; 64] {
+    element.to_bytes_uncompressed()
+}
+
+// No subgroup check is done, the bytes are expected to come from a trusted source
+pub fn element_from_uncompressed(bytes: &[u8]) -> Result<Element, CodecError> {
+    let bytes: [u8; 64] = to_array(bytes)?;
+    Ok(Element::from_bytes_unchecked_uncompressed(bytes))
+}
+
+pub fn element_to_canonical_uncompressed(element: &Element) -> [u8; 64] {
+    canonical_uncompressed(element)
+}
+
+// Hex
+
+pub fn format_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes)
+}
+
+// How commitments and scalars are printed in traces and key explanations:
+// the compressed point and the little endian scalar, in hex
+pub fn format_point(element: &Element) -> String {
+    format_hex(&element_to_compressed(element))
+}
+
+pub fn format_scalar(scalar: &Fr) -> String {
+    format_hex(&fr_to_le_bytes(scalar))
+}
+
+// Accepts an optional 0x prefix
+pub fn parse_hex<const N: usize>(s: &str) -> Result<[u8; N], CodecError> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    to_array(&hex::decode(s)?)
+}
+
+pub fn fr_from_hex(s: &str) -> Result<Fr, CodecError> {
+    fr_from_le_bytes_strict(&parse_hex::<32>(s)?)
+}
+
+pub fn element_from_hex(s: &str) -> Result<Element, CodecError> {
+    element_from_compressed(&parse_hex::<32>(s)?)
+}
+
+// go-verkle conventions: commitments are the 32 byte compressed encoding,
+// and a node hash is group_to_field of the commitment in little endian.
+// Stems and leaf values are interpreted as little endian integers.
+
+pub fn go_verkle_commitment_bytes(commitment: &Element) -> [u8; 32] {
+    element_to_compressed(commitment)
+}
+
+pub fn go_verkle_hash_bytes(commitment: &Element) -> [u8; 32] {
+    fr_to_le_bytes(&group_to_field(commitment))
+}
+
+pub fn go_verkle_stem_scalar(stem: &[u8; 31]) -> Fr {
+    fr_from_le_bytes_mod_order(stem)
+}
+
+pub fn check_codec_round_trips() {
+    let scalar = Fr::from(0x0102030405060708u64);
+    let le = fr_to_le_bytes(&scalar);
+    assert_eq!(le[0], 0x08);
+    assert_eq!(fr_from_le_bytes_strict(&le).unwrap(), scalar);
+    assert_eq!(
+        fr_from_be_bytes_strict(&fr_to_be_bytes(&scalar)).unwrap(),
+        scalar
+    );
+    assert_eq!(
+        fr_from_hex(&format!("0x{}", format_hex(&le))).unwrap(),
+        scalar
+    );
+
+    // 2^256 - 1 is larger than the field order
+    assert_eq!(
+        fr_from_le_bytes_strict(&[0xff; 32]),
+        Err(CodecError::NonCanonicalScalar)
+    );
+    assert_eq!(
+        fr_from_le_bytes_strict(&[0u8; 31]),
+        Err(CodecError::InvalidLength {
+            expected: 32,
+            got: 31
+        })
+    );
+
+    let element = Element::prime_subgroup_generator() + Element::prime_subgroup_generator();
+    let compressed = element_to_compressed(&element);
+    assert_eq!(element_from_compressed(&compressed).unwrap(), element);
+    assert_eq!(element_from_hex(&format_hex(&compressed)).unwrap(), element);
+    assert_eq!(
+        element_from_uncompressed(&element_to_uncompressed(&element)).unwrap(),
+        element
+    );
+    assert_eq!(
+        element_to_canonical_uncompressed(&element),
+        element_to_canonical_uncompressed(&-(-element))
+    );
+
+    assert!(matches!(
+        parse_hex::<32>("0xzz"),
+        Err(CodecError::InvalidHex(_))
+    ));
+}
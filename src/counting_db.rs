@@ -0,0 +1,345 @@
+use std::cell::Cell;
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    proof::prover,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub get_branch_meta: u64,
+    pub get_branch_child: u64,
+    pub get_branch_children: u64,
+    pub get_stem_meta: u64,
+    pub get_stem_children: u64,
+    pub get_leaf: u64,
+    pub insert_leaf: u64,
+    pub insert_stem: u64,
+    pub add_stem_as_branch_child: u64,
+    pub insert_branch: u64,
+}
+
+impl AccessCounts {
+    pub fn reads(&self) -> u64 {
+        self.get_branch_meta
+            + self.get_branch_child
+            + self.get_branch_children
+            + self.get_stem_meta
+            + self.get_stem_children
+            + self.get_leaf
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.insert_leaf + self.insert_stem + self.add_stem_as_branch_child + self.insert_branch
+    }
+
+    fn fields(&self) -> [u64; 10] {
+        [
+            self.get_branch_meta,
+            self.get_branch_child,
+            self.get_branch_children,
+            self.get_stem_meta,
+            self.get_stem_children,
+            self.get_leaf,
+            self.insert_leaf,
+            self.insert_stem,
+            self.add_stem_as_branch_child,
+            self.insert_branch,
+        ]
+    }
+}
+
+// Counts every call made to the wrapped database, and prints each one when logging is on
+pub struct CountingDb<DB> {
+    inner: DB,
+    counts: Cell<AccessCounts>,
+    log: bool,
+}
+
+impl<DB> CountingDb<DB> {
+    pub fn new(inner: DB) -> CountingDb<DB> {
+        CountingDb {
+            inner,
+            counts: Cell::new(AccessCounts::default()),
+            log: false,
+        }
+    }
+
+    pub fn with_logging(inner: DB) -> CountingDb<DB> {
+        CountingDb {
+            log: true,
+            ..CountingDb::new(inner)
+        }
+    }
+
+    pub fn set_logging(&mut self, log: bool) {
+        self.log = log;
+    }
+
+    pub fn counts(&self) -> AccessCounts {
+        self.counts.get()
+    }
+
+    // Returns the counts so far and starts over from zero
+    pub fn take_counts(&self) -> AccessCounts {
+        self.counts.replace(AccessCounts::default())
+    }
+
+    pub fn into_inner(self) -> DB {
+        self.inner
+    }
+
+    fn record(&self, call: std::fmt::Arguments, f: impl FnOnce(&mut AccessCounts)) {
+        let mut counts = self.counts.get();
+        f(&mut counts);
+        self.counts.set(counts);
+        if self.log {
+            println!("db: {}", call);
+        }
+    }
+}
+
+impl<DB: ReadOnlyHigherDb> ReadOnlyHigherDb for CountingDb<DB> {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.record(
+            format_args!("get_stem_meta {}", hex::encode(stem_key)),
+            |c| c.get_stem_meta += 1,
+        );
+        self.inner.get_stem_meta(stem_key)
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.record(format_args!("get_branch_children {:?}", branch_id), |c| {
+            c.get_branch_children += 1
+        });
+        self.inner.get_branch_children(branch_id)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.record(format_args!("get_branch_meta {:?}", key), |c| {
+            c.get_branch_meta += 1
+        });
+        self.inner.get_branch_meta(key)
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        self.record(
+            format_args!("get_branch_child {:?} {}", branch_id, index),
+            |c| c.get_branch_child += 1,
+        );
+        self.inner.get_branch_child(branch_id, index)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.record(
+            format_args!("get_stem_children {}", hex::encode(stem_key)),
+            |c| c.get_stem_children += 1,
+        );
+        self.inner.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.record(format_args!("get_leaf {}", hex::encode(key)), |c| {
+            c.get_leaf += 1
+        });
+        self.inner.get_leaf(key)
+    }
+}
+
+impl<DB: WriteOnlyHigherDb> WriteOnlyHigherDb for CountingDb<DB> {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        self.record(format_args!("insert_leaf {}", hex::encode(key)), |c| {
+            c.insert_leaf += 1
+        });
+        self.inner.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        self.record(format_args!("insert_stem {}", hex::encode(key)), |c| {
+            c.insert_stem += 1
+        });
+        self.inner.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        self.record(
+            format_args!(
+                "add_stem_as_branch_child {:?} {}",
+                branch_child_id,
+                hex::encode(stem_id)
+            ),
+            |c| c.add_stem_as_branch_child += 1,
+        );
+        self.inner
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        self.record(format_args!("insert_branch {:?}", key), |c| {
+            c.insert_branch += 1
+        });
+        self.inner.insert_branch(key, meta, depth)
+    }
+}
+
+fn counting_trie() -> Trie<CountingDb<MemoryDb>, ipa_multipoint::committer::DefaultCommitter> {
+    let trie = Trie::new(DefaultConfig::new(CountingDb::new(MemoryDb::new())));
+    // Trie::new checks for the root, that is not part of any scenario
+    trie.storage.take_counts();
+    trie
+}
+
+// Every level of the path costs the same accesses: counts[d] is counts[0]
+// plus d times the cost of the first level, for every kind of access
+fn assert_same_cost_per_level(what: &str, counts: &[AccessCounts]) {
+    let base = counts[0].fields();
+    let per_level: Vec<i64> = counts[1]
+        .fields()
+        .iter()
+        .zip(base)
+        .map(|(one, zero)| *one as i64 - zero as i64)
+        .collect();
+    for (depth, level) in counts.iter().enumerate() {
+        let expected: Vec<i64> = base
+            .iter()
+            .zip(&per_level)
+            .map(|(zero, step)| *zero as i64 + depth as i64 * step)
+            .collect();
+        let got: Vec<i64> = level.fields().iter().map(|n| *n as i64).collect();
+        assert_eq!(got, expected, "{} at depth {}: {:?}", what, depth, level);
+    }
+}
+
+// The insert scenarios of trie.rs: a fresh stem, a second leaf in the same stem,
+// an update of an existing leaf, and a chain insert where two stems share their
+// first 29 bytes. The fresh stem and chain insert cases are then repeated at every
+// depth to pin down what each level of the path costs.
+pub fn count_insert_accesses() {
+    let mut trie = counting_trie();
+
+    let key_a = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    trie.insert_single(key_a, key_a);
+    let fresh = trie.storage.take_counts();
+    println!("insert into an empty trie: {:?}", fresh);
+    // A new leaf, its stem, the stem under the root and the root
+    assert_eq!(
+        (
+            fresh.insert_leaf,
+            fresh.insert_stem,
+            fresh.add_stem_as_branch_child,
+            fresh.insert_branch
+        ),
+        (1, 1, 1, 1)
+    );
+
+    let mut key_c = key_a;
+    key_c[31] = 0xff;
+    trie.insert_single(key_c, key_c);
+    let existing_stem = trie.storage.take_counts();
+    println!("insert into an existing stem: {:?}", existing_stem);
+
+    trie.insert_single(key_a, [0u8; 32]);
+    let update = trie.storage.take_counts();
+    println!("update an existing leaf: {:?}", update);
+    // Both touch the same leaf slot, stem and root, only the old value differs
+    assert_eq!(update, existing_stem);
+
+    // key_b[29] = 1 shares 29 bytes with key_a, so the chain has 29 internal nodes
+    let mut trie = counting_trie();
+    let key_a = [0u8; 32];
+    let mut key_b = [0u8; 32];
+    key_b[29] = 1;
+    trie.insert_single(key_a, key_a);
+    trie.storage.take_counts();
+    trie.insert_single(key_b, key_b);
+    let chain = trie.storage.take_counts();
+    println!("chain insert of 29 internal nodes: {:?}", chain);
+
+    // A chain of `shared` internal nodes for every shared prefix length
+    let chains: Vec<AccessCounts> = (1..=30)
+        .map(|shared| {
+            let mut trie = counting_trie();
+            trie.insert_single(key_a, key_a);
+            let mut key = key_a;
+            key[shared] = 1;
+            trie.storage.take_counts();
+            trie.insert_single(key, key);
+            trie.storage.take_counts()
+        })
+        .collect();
+    assert_eq!(chains[28], chain);
+    assert_same_cost_per_level("chain insert", &chains);
+
+    // A new stem after falling through `depth` internal nodes. The chain of 30 zero
+    // bytes leaves index 1 free in every branch along it.
+    let mut trie = counting_trie();
+    let mut key_c = key_a;
+    key_c[30] = 1;
+    trie.insert_single(key_a, key_a);
+    trie.insert_single(key_c, key_c);
+    trie.storage.take_counts();
+    let fall_throughs: Vec<AccessCounts> = (0..30)
+        .map(|depth| {
+            let mut key = key_a;
+            key[depth] = 1;
+            trie.insert_single(key, key);
+            trie.storage.take_counts()
+        })
+        .collect();
+    for (depth, counts) in fall_throughs.iter().enumerate() {
+        // Every branch from the new stem's parent up to the root is written once
+        assert_eq!(
+            (
+                counts.insert_leaf,
+                counts.insert_stem,
+                counts.add_stem_as_branch_child,
+                counts.insert_branch
+            ),
+            (1, 1, 1, depth as u64 + 1),
+            "new stem at depth {}",
+            depth
+        );
+    }
+    assert_same_cost_per_level("fall through", &fall_throughs);
+}
+
+// The proof scenarios of proof.rs: presence proofs for a few keys and a proof of absence
+pub fn count_proof_accesses() {
+    let mut trie = counting_trie();
+
+    let mut keys = Vec::new();
+    for i in 0..3 {
+        let mut key = [0u8; 32];
+        key[31] = 0xff - i as u8;
+        keys.push(key);
+        trie.insert_single(key, key);
+    }
+    trie.storage.take_counts();
+
+    let proof = prover::create_verkle_proof(&trie.storage, keys.clone()).unwrap();
+    let counts = trie.storage.take_counts();
+    println!("proof for {} keys: {:?}", keys.len(), counts);
+    assert_eq!(counts.writes(), 0);
+
+    let root = trie.root_commitment();
+    let values = keys.iter().map(|key| Some(*key)).collect();
+    let (ok, _) = proof.check(keys, values, root);
+    assert!(ok);
+
+    let absent = vec![[1; 32]];
+    let _ = prover::create_verkle_proof(&trie.storage, absent).unwrap();
+    println!("proof of absence: {:?}", trie.storage.take_counts());
+}
@@ -0,0 +1,522 @@
+use sha3::{Digest, Keccak256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::codec::{element_from_compressed, element_to_compressed};
+
+const TAG_LEAF: u8 = 0;
+const TAG_STEM: u8 = 1;
+const TAG_BRANCH: u8 = 2;
+const TAG_STEM_CHILD: u8 = 3;
+
+// tag (1) + payload length (4)
+const HEADER_SIZE: usize = 5;
+const CHECKSUM_SIZE: usize = 4;
+// A stem is the longest path a branch child can have
+const MAX_PATH_LEN: usize = 31;
+
+// A database that appends every write to a log file and keeps the whole state
+// in a `MemoryDb` index. Reopening the file replays the log into the index.
+//
+// Record layout: tag | payload length (u32 LE) | payload | first 4 bytes of keccak(tag | length | payload)
+// Every payload starts with the depth the node was written at.
+//
+// Commitments are stored compressed, hashes are recomputed on replay.
+//
+// The `WriteOnlyHigherDb` methods cannot return an error, so a failed append is kept
+// and reported by the next `sync` or `compact`. Nothing is appended after a failure,
+// since the record that failed may have been written in part.
+#[derive(Debug)]
+pub struct FileDb {
+    path: PathBuf,
+    file: File,
+    index: MemoryDb,
+    // `MemoryDb` drops the depth, compaction needs it to write the records back
+    stem_depths: HashMap<[u8; 31], u8>,
+    log_size: u64,
+    truncated_tail: u64,
+    write_error: Option<io::Error>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DecodeError {
+    // The header is valid but the bytes end before the frame does
+    Incomplete,
+    // The tag or the payload length cannot belong to any record
+    BadHeader,
+    // The frame is complete but its checksum or payload is wrong
+    Corrupt { frame_size: usize },
+}
+
+#[derive(Debug)]
+enum Record {
+    Leaf([u8; 32], [u8; 32], u8),
+    Stem([u8; 31], StemMeta, u8),
+    Branch(Vec<u8>, BranchMeta, u8),
+    StemChild(Vec<u8>, [u8; 31], u8),
+}
+
+// Whether a payload of `len` bytes fits a record with this tag
+fn valid_payload_len(tag: u8, len: usize) -> bool {
+    let path_len = |fixed: usize| len >= fixed && len - fixed <= MAX_PATH_LEN;
+    match tag {
+        TAG_LEAF => len == 1 + 64,
+        TAG_STEM => len == 1 + 31 + 3 * 32,
+        TAG_BRANCH => path_len(1 + 1 + 32),
+        TAG_STEM_CHILD => path_len(1 + 1 + 31),
+        _ => false,
+    }
+}
+
+impl Record {
+    fn encode(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Record::Leaf(key, value, depth) => {
+                (TAG_LEAF, [&[*depth], &key[..], &value[..]].concat())
+            }
+            Record::Stem(stem, meta, depth) => (
+                TAG_STEM,
+                [
+                    &[*depth],
+                    &stem[..],
+                    &element_to_compressed(&meta.c_1),
+                    &element_to_compressed(&meta.c_2),
+                    &element_to_compressed(&meta.stem_commitment),
+                ]
+                .concat(),
+            ),
+            Record::Branch(path, meta, depth) => (
+                TAG_BRANCH,
+                [
+                    &[*depth],
+                    &[path.len() as u8],
+                    &path[..],
+                    &element_to_compressed(&meta.commitment),
+                ]
+                .concat(),
+            ),
+            Record::StemChild(path, stem, depth) => (
+                TAG_STEM_CHILD,
+                [&[*depth], &[path.len() as u8], &path[..], &stem[..]].concat(),
+            ),
+        };
+
+        let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len() + CHECKSUM_SIZE);
+        frame.push(tag);
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        let checksum = Keccak256::digest(&frame);
+        frame.extend_from_slice(&checksum[..CHECKSUM_SIZE]);
+        frame
+    }
+
+    // Returns the record and the size of its frame.
+    // A length that no record with this tag can have is a `BadHeader` even if the frame
+    // would run past the end, so a corrupt length is never mistaken for a torn write.
+    fn decode(bytes: &[u8]) -> Result<(Record, usize), DecodeError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DecodeError::Incomplete);
+        }
+        let tag = bytes[0];
+        let len = u32::from_le_bytes(bytes[1..HEADER_SIZE].try_into().unwrap()) as usize;
+        if !valid_payload_len(tag, len) {
+            return Err(DecodeError::BadHeader);
+        }
+        let frame_size = HEADER_SIZE + len + CHECKSUM_SIZE;
+        if bytes.len() < frame_size {
+            return Err(DecodeError::Incomplete);
+        }
+        let corrupt = DecodeError::Corrupt { frame_size };
+        let body = &bytes[..HEADER_SIZE + len];
+        if Keccak256::digest(body)[..CHECKSUM_SIZE] != bytes[HEADER_SIZE + len..frame_size] {
+            return Err(corrupt);
+        }
+
+        let depth = body[HEADER_SIZE];
+        let payload = &body[HEADER_SIZE + 1..];
+        let len = len - 1;
+        let record = match tag {
+            TAG_LEAF => Record::Leaf(
+                payload[0..32].try_into().unwrap(),
+                payload[32..64].try_into().unwrap(),
+                depth,
+            ),
+            TAG_STEM => {
+                let c_1 = element_from_compressed(&payload[31..63]).map_err(|_| corrupt)?;
+                let c_2 = element_from_compressed(&payload[63..95]).map_err(|_| corrupt)?;
+                let stem_commitment =
+                    element_from_compressed(&payload[95..127]).map_err(|_| corrupt)?;
+                Record::Stem(
+                    payload[0..31].try_into().unwrap(),
+                    StemMeta {
+                        c_1,
+                        hash_c1: group_to_field(&c_1),
+                        c_2,
+                        hash_c2: group_to_field(&c_2),
+                        stem_commitment,
+                        hash_stem_commitment: group_to_field(&stem_commitment),
+                    },
+                    depth,
+                )
+            }
+            TAG_BRANCH if len == 1 + payload[0] as usize + 32 => {
+                let path_len = payload[0] as usize;
+                let commitment =
+                    element_from_compressed(&payload[1 + path_len..]).map_err(|_| corrupt)?;
+                Record::Branch(
+                    payload[1..1 + path_len].to_vec(),
+                    BranchMeta {
+                        commitment,
+                        hash_commitment: group_to_field(&commitment),
+                    },
+                    depth,
+                )
+            }
+            TAG_STEM_CHILD if len == 1 + payload[0] as usize + 31 => {
+                let path_len = payload[0] as usize;
+                Record::StemChild(
+                    payload[1..1 + path_len].to_vec(),
+                    payload[1 + path_len..].try_into().unwrap(),
+                    depth,
+                )
+            }
+            _ => return Err(corrupt),
+        };
+        Ok((record, frame_size))
+    }
+
+    fn apply(self, index: &mut MemoryDb, stem_depths: &mut HashMap<[u8; 31], u8>) {
+        match self {
+            Record::Leaf(key, value, depth) => {
+                index.insert_leaf(key, value, depth);
+            }
+            Record::Stem(stem, meta, depth) => {
+                stem_depths.insert(stem, depth);
+                index.insert_stem(stem, meta, depth);
+            }
+            Record::Branch(path, meta, depth) => {
+                index.insert_branch(path, meta, depth);
+            }
+            Record::StemChild(path, stem, depth) => {
+                index.add_stem_as_branch_child(path, stem, depth);
+            }
+        }
+    }
+}
+
+impl FileDb {
+    // Opens or creates the log at `path`.
+    // A torn record at the end of the log, left behind by a crash in the middle of
+    // a write, is cut off so that later appends start from the last intact record.
+    // A bad record followed by more data, or a header no record can have,
+    // is not a torn write, so it is reported as `InvalidData` and the file is left as it is.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<FileDb> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut index = MemoryDb::new();
+        let mut stem_depths = HashMap::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            match Record::decode(&bytes[offset..]) {
+                Ok((record, size)) => {
+                    record.apply(&mut index, &mut stem_depths);
+                    offset += size;
+                }
+                // The header is valid and the frame runs past the end of the file
+                Err(DecodeError::Incomplete) => break,
+                // The last frame, written in full but torn in its contents
+                Err(DecodeError::Corrupt { frame_size }) if offset + frame_size == bytes.len() => {
+                    break
+                }
+                Err(DecodeError::BadHeader) | Err(DecodeError::Corrupt { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "corrupt record at byte {} of {}, followed by {} more bytes",
+                            offset,
+                            path.display(),
+                            bytes.len() - offset
+                        ),
+                    ))
+                }
+            }
+        }
+
+        let truncated_tail = (bytes.len() - offset) as u64;
+        if truncated_tail > 0 {
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+
+        Ok(FileDb {
+            path,
+            file,
+            index,
+            stem_depths,
+            log_size: offset as u64,
+            truncated_tail,
+            write_error: None,
+        })
+    }
+
+    // Bytes of a torn last record that `open` cut off, 0 if the log was intact
+    pub fn truncated_tail(&self) -> u64 {
+        self.truncated_tail
+    }
+
+    pub fn log_size(&self) -> u64 {
+        self.log_size
+    }
+
+    // Also reports an append that failed since the log was opened
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.check_write_error()?;
+        self.file.sync_data()
+    }
+
+    fn check_write_error(&self) -> io::Result<()> {
+        match &self.write_error {
+            Some(err) => Err(io::Error::new(
+                err.kind(),
+                format!("appending to {} failed: {}", self.path.display(), err),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // Rewrites the log so that it only holds the latest record for every key.
+    // The new log is written next to the old one and renamed over it,
+    // so a crash during compaction leaves the old log untouched.
+    // The directory is synced after the rename, otherwise the rename itself may be lost.
+    pub fn compact(&mut self) -> io::Result<CompactionStats> {
+        self.check_write_error()?;
+        let bytes_before = self.log_size;
+
+        let mut compacted_path = self.path.clone().into_os_string();
+        compacted_path.push(".compact");
+        let compacted_path = PathBuf::from(compacted_path);
+
+        let mut out = io::BufWriter::new(File::create(&compacted_path)?);
+        let mut bytes_after = 0u64;
+        for record in self.live_records() {
+            let frame = record.encode();
+            out.write_all(&frame)?;
+            bytes_after += frame.len() as u64;
+        }
+        out.into_inner()?.sync_all()?;
+
+        fs::rename(&compacted_path, &self.path)?;
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.log_size = bytes_after;
+
+        Ok(CompactionStats {
+            bytes_before,
+            bytes_after,
+        })
+    }
+
+    // Sorted, so that compacting the same state always gives the same file
+    fn live_records(&self) -> Vec<Record> {
+        let mut branches: Vec<_> = self.index.branch_table.iter().collect();
+        branches.sort_by(|a, b| a.0.cmp(b.0));
+        let mut stems: Vec<_> = self.index.stem_table.iter().collect();
+        stems.sort_by(|a, b| a.0.cmp(b.0));
+        let mut leaves: Vec<_> = self.index.leaf_table.iter().collect();
+        leaves.sort_by(|a, b| a.0.cmp(b.0));
+
+        // A branch is written at its own path length and a stem child at its parent's,
+        // the same as `insert_single` does
+        let stem_depth = |stem: &[u8; 31]| self.stem_depths.get(stem).copied().unwrap_or(0);
+        let mut records = Vec::new();
+        for (path, child) in branches {
+            records.push(match child {
+                BranchChild::Branch(meta) => Record::Branch(path.clone(), *meta, path.len() as u8),
+                BranchChild::Stem(stem) => {
+                    Record::StemChild(path.clone(), *stem, path.len() as u8 - 1)
+                }
+            });
+        }
+        for (stem, meta) in stems {
+            records.push(Record::Stem(*stem, *meta, stem_depth(stem)));
+        }
+        for (key, value) in leaves {
+            let stem = key[..31].try_into().unwrap();
+            records.push(Record::Leaf(*key, *value, stem_depth(&stem)));
+        }
+        records
+    }
+
+    fn append(&mut self, record: &Record) {
+        if self.write_error.is_some() {
+            return;
+        }
+        let frame = record.encode();
+        match self.file.write_all(&frame) {
+            Ok(()) => self.log_size += frame.len() as u64,
+            Err(err) => self.write_error = Some(err),
+        }
+    }
+}
+
+impl ReadOnlyHigherDb for FileDb {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.index.get_stem_meta(stem_key)
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.index.get_branch_children(branch_id)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.index.get_branch_meta(key)
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        self.index.get_branch_child(branch_id, index)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.index.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.index.get_leaf(key)
+    }
+}
+
+impl WriteOnlyHigherDb for FileDb {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        self.append(&Record::Leaf(key, value, depth));
+        self.index.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        self.append(&Record::Stem(key, meta, depth));
+        self.stem_depths.insert(key, depth);
+        self.index.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        self.append(&Record::StemChild(branch_child_id.clone(), stem_id, depth));
+        self.index
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        self.append(&Record::Branch(key.clone(), meta, depth));
+        self.index.insert_branch(key, meta, depth)
+    }
+}
+
+pub fn file_db_survives_restart() {
+    let path = std::env::temp_dir().join(format!("verkle_file_db_{}.log", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let key_a = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    let mut key_b = key_a;
+    key_b[30] = 0xff;
+
+    let root = {
+        let mut trie = Trie::new(DefaultConfig::new(FileDb::open(&path).unwrap()));
+        trie.insert_single(key_a, key_a);
+        trie.insert_single(key_b, key_b);
+        trie.insert_single(key_a, [0u8; 32]);
+        trie.storage.sync().unwrap();
+        trie.root_hash()
+    };
+
+    // Simulate a crash halfway through appending a record
+    let intact_size = fs::metadata(&path).unwrap().len();
+    let torn = Record::Leaf([0xee; 32], [0xee; 32], 0).encode();
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&torn[..torn.len() / 2]).unwrap();
+    drop(file);
+
+    let mut trie = Trie::new(DefaultConfig::new(FileDb::open(&path).unwrap()));
+    assert_eq!(trie.storage.truncated_tail(), (torn.len() / 2) as u64);
+    assert_eq!(fs::metadata(&path).unwrap().len(), intact_size);
+    assert_eq!(trie.root_hash(), root);
+    assert_eq!(trie.get(key_a), Some([0u8; 32]));
+    assert_eq!(trie.get(key_b), Some(key_b));
+    assert_eq!(trie.get([0xee; 32]), None);
+
+    let stats = trie.storage.compact().unwrap();
+    println!("compaction: {:?}", stats);
+    assert!(stats.bytes_after < stats.bytes_before);
+
+    let trie = Trie::new(DefaultConfig::new(FileDb::open(&path).unwrap()));
+    assert_eq!(trie.storage.truncated_tail(), 0);
+    assert_eq!(trie.root_hash(), root);
+    assert_eq!(trie.get(key_a), Some([0u8; 32]));
+    drop(trie);
+
+    // A full last frame with a bad checksum is also a torn write
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    let mut garbled = torn.clone();
+    garbled[HEADER_SIZE] ^= 0xff;
+    file.write_all(&garbled).unwrap();
+    drop(file);
+    let db = FileDb::open(&path).unwrap();
+    assert_eq!(db.truncated_tail(), garbled.len() as u64);
+    drop(db);
+
+    // A corrupt record in the middle is an error, and nothing is truncated
+    let mut bytes = fs::read(&path).unwrap();
+    bytes[HEADER_SIZE] ^= 0xff;
+    fs::write(&path, &bytes).unwrap();
+    let err = FileDb::open(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+
+    // So is a length no record can have, even though the frame it declares runs past the end
+    bytes[HEADER_SIZE] ^= 0xff;
+    bytes[1..HEADER_SIZE].copy_from_slice(&u32::MAX.to_le_bytes());
+    fs::write(&path, &bytes).unwrap();
+    let err = FileDb::open(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(fs::read(&path).unwrap(), bytes);
+
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,468 @@
+use banderwagon::{Fr, PrimeField};
+use ffi_interface::{get_tree_key, Context};
+use ipa_multipoint::committer::DefaultCommitter;
+use serde_json::Value;
+use sha3::{Digest, Keccak256};
+use std::fmt;
+use std::ops::Mul;
+use std::path::Path;
+use verkle_trie::{
+    constants::CRS, database::memory_db::MemoryDb, group_to_field, trie::Trie, DefaultConfig,
+    TrieTrait,
+};
+
+use crate::batch::batch_insert;
+use crate::codec::{element_to_compressed, format_hex, fr_to_le_bytes, parse_hex, CodecError};
+
+// Account header layout of the current EIP-6800, with version, code size, nonce and
+// balance packed into the basic data leaf. This replaced the earlier layout with one
+// leaf per field, so roots computed here only match networks using the packed header.
+pub const BASIC_DATA_LEAF_KEY: u8 = 0;
+pub const CODE_HASH_LEAF_KEY: u8 = 1;
+pub const HEADER_STORAGE_OFFSET: u64 = 64;
+pub const CODE_OFFSET: u64 = 128;
+pub const VERKLE_NODE_WIDTH: u64 = 256;
+
+// Offsets into the basic data leaf, every field is big endian:
+// version (1) | reserved (4) | code size (3) | nonce (8) | balance (16)
+const BASIC_DATA_VERSION_OFFSET: usize = 0;
+const BASIC_DATA_CODE_SIZE_OFFSET: usize = 5;
+const BASIC_DATA_NONCE_OFFSET: usize = 8;
+const BASIC_DATA_BALANCE_OFFSET: usize = 16;
+
+const PUSH1: u8 = 0x60;
+const PUSH32: u8 = 0x7f;
+
+#[derive(Debug)]
+pub enum GenesisError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    MissingAlloc,
+    InvalidAddress(String),
+    InvalidNumber(String),
+    InvalidHex { field: String, err: CodecError },
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::Io(err) => write!(f, "io error: {}", err),
+            GenesisError::Json(err) => write!(f, "json error: {}", err),
+            GenesisError::MissingAlloc => write!(f, "genesis has no alloc object"),
+            GenesisError::InvalidAddress(address) => write!(f, "invalid address {}", address),
+            GenesisError::InvalidNumber(number) => write!(f, "invalid number {}", number),
+            GenesisError::InvalidHex { field, err } => write!(f, "{}: {}", field, err),
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportStats {
+    pub accounts: usize,
+    pub code_chunks: usize,
+    pub storage_slots: usize,
+    pub leaves: usize,
+}
+
+fn tree_index_le(tree_index: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[..8].copy_from_slice(&tree_index.to_le_bytes());
+    bytes
+}
+
+fn be_to_le(mut bytes: [u8; 32]) -> [u8; 32] {
+    bytes.reverse();
+    bytes
+}
+
+// The last `N` bytes of a big endian number, if the bytes above them are all zero
+fn fit_be<const N: usize>(number: &[u8; 32], name: &str) -> Result<[u8; N], GenesisError> {
+    let (high, low) = number.split_at(32 - N);
+    if high.iter().any(|b| *b != 0) {
+        return Err(GenesisError::InvalidNumber(format!(
+            "{} 0x{} does not fit in {} bytes",
+            name,
+            hex::encode(number),
+            N
+        )));
+    }
+    Ok(low.try_into().unwrap())
+}
+
+pub fn basic_data_leaf(
+    version: u8,
+    code_size: u64,
+    nonce: u64,
+    balance: &[u8; 32],
+) -> Result<[u8; 32], GenesisError> {
+    let code_size: [u8; 3] = fit_be(&u64_to_be(code_size), "code size")?;
+    let balance: [u8; 16] = fit_be(balance, "balance")?;
+
+    let mut leaf = [0u8; 32];
+    leaf[BASIC_DATA_VERSION_OFFSET] = version;
+    leaf[BASIC_DATA_CODE_SIZE_OFFSET..BASIC_DATA_NONCE_OFFSET].copy_from_slice(&code_size);
+    leaf[BASIC_DATA_NONCE_OFFSET..BASIC_DATA_BALANCE_OFFSET].copy_from_slice(&nonce.to_be_bytes());
+    leaf[BASIC_DATA_BALANCE_OFFSET..].copy_from_slice(&balance);
+    Ok(leaf)
+}
+
+fn u64_to_be(n: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[24..].copy_from_slice(&n.to_be_bytes());
+    bytes
+}
+
+pub fn address_to_bytes32(address: &[u8; 20]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[12..].copy_from_slice(address);
+    bytes
+}
+
+pub fn header_key(context: &Context, address: &[u8; 20], leaf: u8) -> [u8; 32] {
+    get_tree_key(context, address_to_bytes32(address), tree_index_le(0), leaf)
+}
+
+pub fn code_chunk_key(context: &Context, address: &[u8; 20], chunk_id: u64) -> [u8; 32] {
+    let position = CODE_OFFSET + chunk_id;
+    get_tree_key(
+        context,
+        address_to_bytes32(address),
+        tree_index_le(position / VERKLE_NODE_WIDTH),
+        (position % VERKLE_NODE_WIDTH) as u8,
+    )
+}
+
+// Big endian tree index and sub index of a storage slot. The first 64 slots
+// live in the account header stem, every other slot is placed at
+// MAIN_STORAGE_OFFSET (256^31) + slot. That position can exceed 2^256, so the
+// tree index is computed as slot / 256 + 256^30 instead, like geth's StorageIndex.
+pub(crate) fn storage_slot_position(slot: &[u8; 32]) -> ([u8; 32], u8) {
+    let header_slots = CODE_OFFSET - HEADER_STORAGE_OFFSET;
+    let is_header_slot = slot[..31].iter().all(|b| *b == 0) && (slot[31] as u64) < header_slots;
+    if is_header_slot {
+        return ([0u8; 32], slot[31] + HEADER_STORAGE_OFFSET as u8);
+    }
+
+    let mut tree_index = [0u8; 32];
+    tree_index[1..].copy_from_slice(&slot[..31]);
+    // + 256^30, carrying into the top byte. slot / 256 < 256^31, so this cannot overflow.
+    for byte in tree_index[..2].iter_mut().rev() {
+        let (sum, carry) = byte.overflowing_add(1);
+        *byte = sum;
+        if !carry {
+            break;
+        }
+    }
+    (tree_index, slot[31])
+}
+
+pub fn storage_slot_key(context: &Context, address: &[u8; 20], slot: &[u8; 32]) -> [u8; 32] {
+    let (tree_index, sub_index) = storage_slot_position(slot);
+    get_tree_key(
+        context,
+        address_to_bytes32(address),
+        be_to_le(tree_index),
+        sub_index,
+    )
+}
+
+// get_tree_key as written in EIP-6800: the 64 input bytes are split into four
+// 16 byte little endian integers, preceded by 2 + 256 * 64, and committed to
+// with the CRS. The stem is the first 31 bytes of the commitment's field hash.
+// Kept independent of `ffi_interface` so the two can be checked against each other.
+fn spec_tree_key(address: &[u8; 32], tree_index_le: &[u8; 32], sub_index: u8) -> [u8; 32] {
+    let mut input = [0u8; 64];
+    input[..32].copy_from_slice(address);
+    input[32..].copy_from_slice(tree_index_le);
+
+    let mut commitment = CRS[0].mul(Fr::from(2u64 + 256 * 64));
+    for (i, chunk) in input.chunks(16).enumerate() {
+        commitment = commitment + CRS[i + 1].mul(Fr::from_le_bytes_mod_order(chunk));
+    }
+
+    let mut key = fr_to_le_bytes(&group_to_field(&commitment));
+    key[31] = sub_index;
+    key
+}
+
+// 31 byte chunks, each prefixed with the number of leading bytes that are
+// PUSH data of an instruction from an earlier chunk
+pub fn chunkify_code(code: &[u8]) -> Vec<[u8; 32]> {
+    let mut padded = code.to_vec();
+    if padded.len() % 31 != 0 {
+        padded.resize(padded.len() + 31 - padded.len() % 31, 0);
+    }
+
+    let mut pushdata_remaining = vec![0u8; padded.len()];
+    let mut pos = 0;
+    while pos < padded.len() {
+        let pushdata_bytes = if (PUSH1..=PUSH32).contains(&padded[pos]) {
+            (padded[pos] - PUSH1 + 1) as usize
+        } else {
+            0
+        };
+        pos += 1;
+        for x in 0..pushdata_bytes {
+            if pos + x < padded.len() {
+                pushdata_remaining[pos + x] = (pushdata_bytes - x) as u8;
+            }
+        }
+        pos += pushdata_bytes;
+    }
+
+    padded
+        .chunks(31)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut bytes = [0u8; 32];
+            bytes[0] = pushdata_remaining[i * 31].min(31);
+            bytes[1..].copy_from_slice(chunk);
+            bytes
+        })
+        .collect()
+}
+
+// Parses a 0x prefixed hex or a decimal quantity into 32 big endian bytes
+fn parse_u256(s: &str) -> Result<[u8; 32], GenesisError> {
+    let invalid = || GenesisError::InvalidNumber(s.to_string());
+
+    if let Some(digits) = s.strip_prefix("0x") {
+        if digits.len() > 64 {
+            return Err(invalid());
+        }
+        let padded = format!("{:0>64}", digits);
+        return parse_hex::<32>(&padded).map_err(|_| invalid());
+    }
+
+    let mut be = [0u8; 32];
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    for c in s.chars() {
+        let digit = c.to_digit(10).ok_or_else(invalid)?;
+        // be = be * 10 + digit
+        let mut carry = digit;
+        for byte in be.iter_mut().rev() {
+            let v = *byte as u32 * 10 + carry;
+            *byte = v as u8;
+            carry = v >> 8;
+        }
+        if carry != 0 {
+            return Err(invalid());
+        }
+    }
+    Ok(be)
+}
+
+fn parse_bytes(field: &str, s: &str) -> Result<Vec<u8>, GenesisError> {
+    hex::decode(s.strip_prefix("0x").unwrap_or(s)).map_err(|err| GenesisError::InvalidHex {
+        field: field.to_string(),
+        err: CodecError::InvalidHex(err),
+    })
+}
+
+fn parse_address(s: &str) -> Result<[u8; 20], GenesisError> {
+    parse_hex::<20>(s).map_err(|_| GenesisError::InvalidAddress(s.to_string()))
+}
+
+fn field_str<'a>(account: &'a Value, field: &str) -> Option<&'a str> {
+    account.get(field).and_then(Value::as_str)
+}
+
+// The leaves for every account in a genesis `alloc` object
+pub fn alloc_leaves(
+    context: &Context,
+    alloc: &Value,
+) -> Result<(Vec<([u8; 32], [u8; 32])>, ImportStats), GenesisError> {
+    let accounts = alloc.as_object().ok_or(GenesisError::MissingAlloc)?;
+    let mut leaves = Vec::new();
+    let mut stats = ImportStats::default();
+
+    for (address, account) in accounts {
+        let address = parse_address(address)?;
+        stats.accounts += 1;
+
+        let balance = match field_str(account, "balance") {
+            Some(balance) => parse_u256(balance)?,
+            None => [0u8; 32],
+        };
+        let nonce = match field_str(account, "nonce") {
+            Some(nonce) => parse_u256(nonce)?,
+            None => [0u8; 32],
+        };
+        let code = match field_str(account, "code") {
+            Some(code) => parse_bytes("code", code)?,
+            None => Vec::new(),
+        };
+        let code_hash: [u8; 32] = Keccak256::digest(&code).into();
+        let nonce = u64::from_be_bytes(fit_be(&nonce, "nonce")?);
+
+        leaves.push((
+            header_key(context, &address, BASIC_DATA_LEAF_KEY),
+            basic_data_leaf(0, code.len() as u64, nonce, &balance)?,
+        ));
+        leaves.push((header_key(context, &address, CODE_HASH_LEAF_KEY), code_hash));
+
+        for (chunk_id, chunk) in chunkify_code(&code).into_iter().enumerate() {
+            leaves.push((code_chunk_key(context, &address, chunk_id as u64), chunk));
+            stats.code_chunks += 1;
+        }
+
+        if let Some(storage) = account.get("storage").and_then(Value::as_object) {
+            for (slot, value) in storage {
+                let slot = parse_u256(slot)?;
+                let value = parse_u256(value.as_str().unwrap_or_default())?;
+                leaves.push((storage_slot_key(context, &address, &slot), value));
+                stats.storage_slots += 1;
+            }
+        }
+    }
+
+    stats.leaves = leaves.len();
+    Ok((leaves, stats))
+}
+
+pub fn import_genesis(
+    genesis: &Value,
+) -> Result<(Trie<MemoryDb, DefaultCommitter>, ImportStats), GenesisError> {
+    let alloc = genesis.get("alloc").ok_or(GenesisError::MissingAlloc)?;
+    let (leaves, stats) = alloc_leaves(&Context::default(), alloc)?;
+
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    batch_insert(&mut trie, leaves);
+    Ok((trie, stats))
+}
+
+pub fn import_genesis_file(
+    path: impl AsRef<Path>,
+) -> Result<(Trie<MemoryDb, DefaultCommitter>, ImportStats), GenesisError> {
+    let bytes = std::fs::read(path).map_err(GenesisError::Io)?;
+    let genesis: Value = serde_json::from_slice(&bytes).map_err(GenesisError::Json)?;
+    import_genesis(&genesis)
+}
+
+pub fn print_genesis_root(path: impl AsRef<Path>) {
+    let (trie, stats) = import_genesis_file(path).unwrap();
+    println!("imported: {:?}", stats);
+    println!(
+        "state root: 0x{}",
+        format_hex(&element_to_compressed(&trie.root_commitment()))
+    );
+}
+
+pub fn import_small_genesis() {
+    let genesis: Value = serde_json::from_str(
+        r#"{
+            "alloc": {
+                "0x0000000000000000000000000000000000000001": { "balance": "1" },
+                "0x1000000000000000000000000000000000000000": {
+                    "balance": "0x3635c9adc5dea00000",
+                    "nonce": "0x1",
+                    "code": "0x6001600055",
+                    "storage": {
+                        "0x00": "0x01",
+                        "0x0100": "0x02"
+                    }
+                }
+            }
+        }"#,
+    )
+    .unwrap();
+
+    let (trie, stats) = import_genesis(&genesis).unwrap();
+    println!("imported: {:?}", stats);
+    assert_eq!(stats.accounts, 2);
+    assert_eq!(stats.code_chunks, 1);
+    assert_eq!(stats.storage_slots, 2);
+
+    let context = Context::default();
+    let address = parse_address("0x1000000000000000000000000000000000000000").unwrap();
+
+    // The header leaves and the first storage slots share one stem
+    let basic_data_key = header_key(&context, &address, BASIC_DATA_LEAF_KEY);
+    let slot_0_key = storage_slot_key(&context, &address, &[0u8; 32]);
+    assert_eq!(basic_data_key[..31], slot_0_key[..31]);
+    assert_eq!(slot_0_key[31], HEADER_STORAGE_OFFSET as u8);
+
+    // Version 0, code size 5, nonce 1 and 1000 ether, all big endian
+    let mut basic_data = [0u8; 32];
+    basic_data[7] = 5;
+    basic_data[15] = 1;
+    basic_data[23..].copy_from_slice(&[0x36, 0x35, 0xc9, 0xad, 0xc5, 0xde, 0xa0, 0x00, 0x00]);
+    assert_eq!(trie.get(basic_data_key), Some(basic_data));
+    assert_eq!(
+        trie.get(header_key(&context, &address, CODE_HASH_LEAF_KEY)),
+        Some(Keccak256::digest([0x60, 0x01, 0x60, 0x00, 0x55]).into())
+    );
+
+    // A balance above 2^128 does not fit the basic data leaf
+    let mut huge_balance = [0u8; 32];
+    huge_balance[15] = 1;
+    assert!(basic_data_leaf(0, 0, 0, &huge_balance).is_err());
+    assert!(basic_data_leaf(0, 1 << 24, 0, &[0u8; 32]).is_err());
+
+    let mut slot_1 = [0u8; 32];
+    slot_1[30] = 1;
+    let mut value = [0u8; 32];
+    value[31] = 2;
+    assert_eq!(
+        trie.get(storage_slot_key(&context, &address, &slot_1)),
+        Some(value)
+    );
+
+    // PUSH1 0x01 PUSH1 0x00 SSTORE fits in the first chunk with nothing carried over
+    let chunks = chunkify_code(&[0x60, 0x01, 0x60, 0x00, 0x55]);
+    assert_eq!(chunks[0][0], 0);
+    assert_eq!(chunks[0][1..6], [0x60, 0x01, 0x60, 0x00, 0x55]);
+
+    // A PUSH32 at the end of a chunk carries its data into the next one
+    let mut code = vec![0u8; 30];
+    code.push(PUSH32);
+    code.extend_from_slice(&[0xff; 32]);
+    let chunks = chunkify_code(&code);
+    assert_eq!(chunks[1][0], 31);
+    assert_eq!(chunks[2][0], 1);
+
+    assert_eq!(
+        parse_u256("1000000000000000000000").unwrap(),
+        parse_u256("0x3635c9adc5dea00000").unwrap()
+    );
+
+    // A slot with a 0xff top byte: slot / 256 + 256^30 carries into the top byte
+    let max_slot = [0xffu8; 32];
+    let mut max_slot_index = [0xffu8; 32];
+    max_slot_index[0] = 0x01;
+    max_slot_index[1] = 0x00;
+    assert_eq!(storage_slot_position(&max_slot), (max_slot_index, 0xff));
+    let mut slot_64 = [0u8; 32];
+    slot_64[31] = 64;
+    let mut expected_index = [0u8; 32];
+    expected_index[1] = 0x01;
+    assert_eq!(storage_slot_position(&slot_64), (expected_index, 64));
+
+    // Every key kind against the EIP-6800 definition of get_tree_key
+    let address32 = address_to_bytes32(&address);
+    assert_eq!(
+        basic_data_key,
+        spec_tree_key(&address32, &tree_index_le(0), BASIC_DATA_LEAF_KEY)
+    );
+    assert_eq!(
+        code_chunk_key(&context, &address, 200),
+        spec_tree_key(&address32, &tree_index_le(1), 72)
+    );
+    assert_eq!(
+        storage_slot_key(&context, &address, &slot_1),
+        spec_tree_key(&address32, &be_to_le(storage_slot_position(&slot_1).0), 0)
+    );
+    assert_eq!(
+        storage_slot_key(&context, &address, &max_slot),
+        spec_tree_key(&address32, &be_to_le(max_slot_index), 0xff)
+    );
+
+    println!(
+        "state root: 0x{}",
+        format_hex(&element_to_compressed(&trie.root_commitment()))
+    );
+}
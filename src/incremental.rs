@@ -0,0 +1,210 @@
+use crate::canonical::canonical_element;
+use crate::codec::fr_to_le_bytes;
+use crate::leaf::{split_value, LeafValue};
+use banderwagon::{Element, Fr};
+use ffi_interface::{update_commitment_sparse, Context};
+use verkle_trie::{
+    database::{memory_db::MemoryDb, meta::BranchMeta, ReadOnlyHigherDb, WriteOnlyHigherDb},
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+#[derive(Debug)]
+pub enum UpdateError {
+    // The updater only propagates value changes, it does not create new stems
+    StemNotFound([u8; 31]),
+    Commitment(ffi_interface::Error),
+}
+
+// Applies leaf value changes to a trie by only using commitment deltas:
+// the leaf delta goes into C1 or C2, the change of hash(C) goes into the stem commitment
+// and the change of the child hash goes into every branch on the path up to the root.
+pub struct IncrementalUpdater {
+    context: Context,
+}
+
+impl Default for IncrementalUpdater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IncrementalUpdater {
+    pub fn new() -> IncrementalUpdater {
+        IncrementalUpdater {
+            context: Context::default(),
+        }
+    }
+
+    // Returns the new root hash
+    pub fn update_leaf(
+        &self,
+        storage: &mut MemoryDb,
+        key: [u8; 32],
+        new_value: [u8; 32],
+    ) -> Result<Fr, UpdateError> {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        let suffix = key[31];
+        let mut stem_meta = storage
+            .get_stem_meta(stem)
+            .ok_or(UpdateError::StemNotFound(stem))?;
+
+        // An absent leaf contributes nothing to C1/C2
+        let (old_low, old_high) = LeafValue::from(storage.get_leaf(key)).to_scalars();
+        let (new_low, new_high) = split_value(&new_value);
+
+        let offset = 2 * (suffix as usize % 128);
+        let (old_c, stem_index) = if suffix < 128 {
+            (stem_meta.c_1, 2)
+        } else {
+            (stem_meta.c_2, 3)
+        };
+        let new_c = self.apply_deltas(
+            old_c,
+            vec![offset, offset + 1],
+            vec![old_low, old_high],
+            vec![new_low, new_high],
+        )?;
+        let old_hash_c = group_to_field(&old_c);
+        let new_hash_c = group_to_field(&new_c);
+        if suffix < 128 {
+            stem_meta.c_1 = new_c;
+            stem_meta.hash_c1 = new_hash_c;
+        } else {
+            stem_meta.c_2 = new_c;
+            stem_meta.hash_c2 = new_hash_c;
+        }
+
+        let old_stem_hash = stem_meta.hash_stem_commitment;
+        stem_meta.stem_commitment = self.apply_deltas(
+            stem_meta.stem_commitment,
+            vec![stem_index],
+            vec![old_hash_c],
+            vec![new_hash_c],
+        )?;
+        stem_meta.hash_stem_commitment = group_to_field(&stem_meta.stem_commitment);
+
+        let branch_path = branch_path_to_stem(storage, stem);
+        // The path includes the root, and the stem gets its parent branch's path length
+        let stem_depth = (branch_path.len() - 1) as u8;
+
+        // Walk back up from the stem's parent to the root, each branch sees the
+        // change in its child's hash at the index of the stem on that level
+        let mut old_child_hash = old_stem_hash;
+        let mut new_child_hash = stem_meta.hash_stem_commitment;
+        let mut new_branches = Vec::with_capacity(branch_path.len());
+        for (path, meta) in branch_path.into_iter().rev() {
+            let child_index = stem[path.len()] as usize;
+            let commitment = self.apply_deltas(
+                meta.commitment,
+                vec![child_index],
+                vec![old_child_hash],
+                vec![new_child_hash],
+            )?;
+            let new_meta = BranchMeta {
+                commitment,
+                hash_commitment: group_to_field(&commitment),
+            };
+            old_child_hash = meta.hash_commitment;
+            new_child_hash = new_meta.hash_commitment;
+            new_branches.push((path, new_meta));
+        }
+
+        storage.insert_leaf(key, new_value, stem_depth);
+        storage.insert_stem(stem, stem_meta, stem_depth);
+        for (path, meta) in new_branches {
+            let depth = path.len() as u8;
+            storage.insert_branch(path, meta, depth);
+        }
+
+        Ok(new_child_hash)
+    }
+
+    fn apply_deltas(
+        &self,
+        commitment: Element,
+        indices: Vec<usize>,
+        old_scalars: Vec<Fr>,
+        new_scalars: Vec<Fr>,
+    ) -> Result<Element, UpdateError> {
+        let updated = update_commitment_sparse(
+            &self.context,
+            commitment.to_bytes_uncompressed(),
+            indices,
+            old_scalars.iter().map(fr_to_le_bytes).collect(),
+            new_scalars.iter().map(fr_to_le_bytes).collect(),
+        )
+        .map_err(UpdateError::Commitment)?;
+
+        // Keep the stored commitments in canonical form, so their bytes can be compared
+        Ok(canonical_element(
+            &Element::from_bytes_unchecked_uncompressed(updated),
+        ))
+    }
+}
+
+// All branches from the root down to the one holding the stem, along with their metas
+fn branch_path_to_stem(storage: &MemoryDb, stem: [u8; 31]) -> Vec<(Vec<u8>, BranchMeta)> {
+    let mut path = vec![];
+    let mut branches = vec![(path.clone(), storage.get_branch_meta(&path).unwrap())];
+    loop {
+        path.push(stem[path.len()]);
+        match storage.get_branch_meta(&path) {
+            Some(meta) => branches.push((path.clone(), meta)),
+            None => return branches,
+        }
+    }
+}
+
+pub fn incremental_matches_insert_single() {
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+
+    let key_a = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    let mut key_b = key_a;
+    key_b[30] = 0xff;
+    let mut key_c = [0u8; 32];
+    key_c[29] = 1;
+
+    trie.insert_single(key_a, key_a);
+    trie.insert_single(key_b, key_b);
+    trie.insert_single([0u8; 32], [0u8; 32]);
+    trie.insert_single(key_c, key_c);
+
+    // Overwrites of existing leaves, a new leaf in C1 and in C2 of an existing stem,
+    // and a write of zero which must still differ from an absent leaf
+    let mut key_a_c2 = key_a;
+    key_a_c2[31] = 200;
+    let mut key_c_c1 = key_c;
+    key_c_c1[31] = 7;
+    let updates = vec![
+        (key_a, [0xaa; 32]),
+        (key_a_c2, key_a),
+        (key_c_c1, [0u8; 32]),
+        (key_b, [0u8; 32]),
+        ([0u8; 32], [0x11; 32]),
+    ];
+
+    let updater = IncrementalUpdater::new();
+    let mut incremental = trie.clone();
+    for (key, value) in updates {
+        let root = updater
+            .update_leaf(&mut incremental.storage, key, value)
+            .unwrap();
+        trie.insert_single(key, value);
+
+        assert_eq!(root, trie.root_hash());
+        assert_eq!(incremental.root_commitment(), trie.root_commitment());
+        assert_eq!(incremental.get(key), Some(value));
+    }
+
+    let mut new_stem = [0x42u8; 32];
+    new_stem[31] = 0;
+    assert!(matches!(
+        updater.update_leaf(&mut incremental.storage, new_stem, new_stem),
+        Err(UpdateError::StemNotFound(_))
+    ));
+}
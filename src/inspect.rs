@@ -0,0 +1,434 @@
+use ipa_multipoint::committer::Committer;
+use std::fmt::{self, Write};
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    trie::{paths_from_relative, Ins, Trie},
+    DefaultConfig, TrieTrait,
+};
+
+use crate::codec::{format_point, format_scalar, parse_hex, CodecError};
+
+fn format_path(path: &[u8]) -> String {
+    if path.is_empty() {
+        return "root".to_string();
+    }
+    let bytes: Vec<_> = path.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("[{}]", bytes.join(" "))
+}
+
+fn commitment_slot(suffix: u8) -> String {
+    let offset = 2 * (suffix as usize % 128);
+    let c = if suffix < 128 { "C1" } else { "C2" };
+    format!("{}[{}, {}]", c, offset, offset + 1)
+}
+
+// Describes a single instruction. The database has to be in the state the
+// instructions were created against, since that is where new stems and old
+// leaf values are looked up.
+pub fn describe_instruction<DB: ReadOnlyHigherDb>(storage: &DB, ins: &Ins) -> String {
+    let mut out = String::new();
+    match ins {
+        Ins::UpdateLeaf {
+            key,
+            new_leaf_value,
+            depth,
+            branch_id,
+            branch_child_index,
+        } => {
+            let stem: [u8; 31] = key[0..31].try_into().unwrap();
+            let kind = if storage.get_stem_meta(stem).is_none() {
+                "new stem"
+            } else if storage.get_leaf(*key).is_none() {
+                "new leaf in existing stem"
+            } else {
+                "update leaf"
+            };
+            writeln!(
+                out,
+                "UpdateLeaf ({}) stem {} under {} at index {} (depth {})",
+                kind,
+                hex::encode(stem),
+                format_path(branch_id),
+                branch_child_index,
+                depth
+            )
+            .unwrap();
+            if let Some(old) = storage.get_leaf(*key) {
+                writeln!(out, "    old value {}", hex::encode(old)).unwrap();
+            }
+            write!(
+                out,
+                "    suffix {} -> {} = {}",
+                key[31],
+                commitment_slot(key[31]),
+                hex::encode(new_leaf_value)
+            )
+            .unwrap();
+        }
+        Ins::ChainInsert {
+            starting_depth,
+            chain_insert_path,
+            parent_branch_node,
+            child_index,
+            old_leaf_index,
+            new_leaf_key,
+            new_leaf_value,
+            new_leaf_index,
+        } => {
+            // The chain path is relative to the parent and starts with the child index,
+            // every prefix of it becomes a new internal node
+            let mut replaced = parent_branch_node.clone();
+            replaced.push(*child_index);
+            writeln!(
+                out,
+                "ChainInsert replaces the stem at {} with a chain of {} internal node(s) from depth {}",
+                format_path(&replaced),
+                chain_insert_path.len(),
+                starting_depth
+            )
+            .unwrap();
+            for path in paths_from_relative(parent_branch_node.clone(), chain_insert_path.clone()) {
+                writeln!(out, "    creates internal node {}", format_path(&path)).unwrap();
+            }
+            let mut last = parent_branch_node.clone();
+            last.extend_from_slice(chain_insert_path);
+            writeln!(
+                out,
+                "    old stem moves to {} index {}",
+                format_path(&last),
+                old_leaf_index
+            )
+            .unwrap();
+            write!(
+                out,
+                "    new stem {} at {} index {}, suffix {} -> {} = {}",
+                hex::encode(&new_leaf_key[..31]),
+                format_path(&last),
+                new_leaf_index,
+                new_leaf_key[31],
+                commitment_slot(new_leaf_key[31]),
+                hex::encode(new_leaf_value)
+            )
+            .unwrap();
+        }
+        Ins::InternalNodeFallThrough {
+            branch_id,
+            branch_child_index,
+            child,
+            old_child_value,
+            depth,
+        } => {
+            write!(
+                out,
+                "InternalNodeFallThrough {} index {} takes the new commitment of {} (depth {}, {})",
+                format_path(branch_id),
+                branch_child_index,
+                format_path(child),
+                depth,
+                if old_child_value.is_some() {
+                    "delta from the old child"
+                } else {
+                    "child is new"
+                }
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+pub fn describe_instructions<DB: ReadOnlyHigherDb>(storage: &DB, instructions: &[Ins]) -> String {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(i, ins)| format!("{:>3}: {}", i, describe_instruction(storage, ins)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Prints the instructions an insert generates, applies them and returns what was printed
+pub fn inspect_insert<DB, C>(trie: &mut Trie<DB, C>, key: [u8; 32], value: [u8; 32]) -> String
+where
+    DB: ReadOnlyHigherDb + WriteOnlyHigherDb,
+    C: Committer,
+{
+    let instructions = trie.create_insert_instructions(key, value);
+    let description = describe_instructions(&trie.storage, &instructions);
+    println!(
+        "insert {} = {}\n{}",
+        hex::encode(key),
+        hex::encode(value),
+        description
+    );
+    trie.process_instructions(instructions);
+    description
+}
+
+fn count_lines(description: &str, pattern: &str) -> usize {
+    description
+        .lines()
+        .filter(|line| line.contains(pattern))
+        .count()
+}
+
+// The inserts of `trie::insert_key0value0` and `trie::insert_and_traverse_longest_path`
+pub fn inspect_trie_scenarios() {
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+
+    let key_a = [0u8; 32];
+    let out = inspect_insert(&mut trie, key_a, key_a);
+    assert_eq!(out.lines().count(), 2);
+    assert!(out.starts_with("  0: UpdateLeaf (new stem) stem 0000"));
+    assert!(out.contains("under root at index 0 (depth 0)"));
+    assert!(out.contains("suffix 0 -> C1[0, 1]"));
+
+    // Same stem, other half of the stem
+    let mut key_b = [0u8; 32];
+    key_b[31] = 0xff;
+    let out = inspect_insert(&mut trie, key_b, key_b);
+    assert!(out.starts_with("  0: UpdateLeaf (new leaf in existing stem)"));
+    assert!(out.contains("suffix 255 -> C2[254, 255]"));
+
+    // Overwrite
+    let out = inspect_insert(&mut trie, key_a, [1u8; 32]);
+    assert!(out.starts_with("  0: UpdateLeaf (update leaf)"));
+    assert!(out.contains(&format!("old value {}", hex::encode(key_a))));
+
+    // Shares 30 bytes with the existing stem, so it needs the longest chain:
+    // one internal node for every shared byte, [00] down to 30 zero bytes
+    let mut key_c = [0u8; 32];
+    key_c[30] = 1;
+    let out = inspect_insert(&mut trie, key_c, key_c);
+    assert_eq!(count_lines(&out, ": ChainInsert"), 1);
+    assert_eq!(count_lines(&out, ": UpdateLeaf"), 0);
+    assert_eq!(count_lines(&out, ": InternalNodeFallThrough"), 0);
+    assert!(
+        out.contains("ChainInsert replaces the stem at [00] with a chain of 30 internal node(s)")
+    );
+    assert_eq!(count_lines(&out, "creates internal node"), 30);
+    let deepest = format_path(&[0u8; 30]);
+    assert!(out.contains(&format!("creates internal node {}\n", deepest)));
+    assert!(out.contains(&format!("old stem moves to {} index 0", deepest)));
+    assert!(out.contains(&format!("at {} index 1", deepest)));
+    for depth in 1..=30 {
+        assert!(trie.storage.get_branch_meta(&[0u8; 30][..depth]).is_some());
+    }
+
+    // Falls through the internal nodes created above, from the root down to [00; 28],
+    // and lands in an empty slot of the branch at 29 zero bytes
+    let mut key_d = [0u8; 32];
+    key_d[29] = 1;
+    let out = inspect_insert(&mut trie, key_d, key_d);
+    assert_eq!(count_lines(&out, ": InternalNodeFallThrough"), 29);
+    assert_eq!(count_lines(&out, ": UpdateLeaf (new stem)"), 1);
+    assert_eq!(count_lines(&out, ": ChainInsert"), 0);
+    assert!(out.contains(&format!(
+        "under {} at index 1 (depth 29)",
+        format_path(&[0u8; 29])
+    )));
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchStep {
+    pub path: Vec<u8>,
+    pub index: u8,
+    pub meta: BranchMeta,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathEnd {
+    // The key's own stem, `c2` says which of C1/C2 holds the suffix
+    Stem {
+        meta: StemMeta,
+        c2: bool,
+        value: Option<[u8; 32]>,
+    },
+    // A different stem sits where the key's stem would be, so the key is absent
+    OtherStem {
+        stem: [u8; 31],
+        shared_prefix: usize,
+        meta: StemMeta,
+    },
+    // Nothing under the last branch at the key's index, so the key is absent
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyExplanation {
+    pub key: [u8; 32],
+    pub steps: Vec<BranchStep>,
+    pub end: PathEnd,
+}
+
+impl KeyExplanation {
+    pub fn value(&self) -> Option<[u8; 32]> {
+        match &self.end {
+            PathEnd::Stem { value, .. } => *value,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for KeyExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "key {}", hex::encode(self.key))?;
+        for step in &self.steps {
+            writeln!(
+                f,
+                "  depth {:>2} {} -> index {:02x}\n      commitment {}\n      hash       {}",
+                step.path.len(),
+                format_path(&step.path),
+                step.index,
+                format_point(&step.meta.commitment),
+                format_scalar(&step.meta.hash_commitment)
+            )?;
+        }
+        match &self.end {
+            PathEnd::Stem { meta, c2, value } => {
+                let (c, hash_c) = if *c2 {
+                    (&meta.c_2, &meta.hash_c2)
+                } else {
+                    (&meta.c_1, &meta.hash_c1)
+                };
+                writeln!(
+                    f,
+                    "  stem {} (the key's stem)\n      commitment {}\n      hash       {}",
+                    hex::encode(&self.key[0..31]),
+                    format_point(&meta.stem_commitment),
+                    format_scalar(&meta.hash_stem_commitment)
+                )?;
+                writeln!(
+                    f,
+                    "  suffix {:02x} -> {}\n      commitment {}\n      hash       {}",
+                    self.key[31],
+                    commitment_slot(self.key[31]),
+                    format_point(c),
+                    format_scalar(hash_c)
+                )?;
+                match value {
+                    Some(value) => write!(f, "  value {}", hex::encode(value)),
+                    None => write!(f, "  value absent (empty suffix slot)"),
+                }
+            }
+            PathEnd::OtherStem {
+                stem,
+                shared_prefix,
+                meta,
+            } => write!(
+                f,
+                "  stem {} (other stem, shares {} bytes)\n      commitment {}\n      hash       {}\n  value absent (proof of absence via the other stem)",
+                hex::encode(stem),
+                shared_prefix,
+                format_point(&meta.stem_commitment),
+                format_scalar(&meta.hash_stem_commitment)
+            ),
+            PathEnd::Empty => write!(f, "  empty slot\n  value absent (proof of absence via the branch)"),
+        }
+    }
+}
+
+// Walks from the root towards the key, the way the prover does for a proof
+pub fn explain_key<DB: ReadOnlyHigherDb>(storage: &DB, key: [u8; 32]) -> KeyExplanation {
+    let stem: [u8; 31] = key[0..31].try_into().unwrap();
+    let mut steps = Vec::new();
+
+    let mut depth = 0;
+    let end = loop {
+        let path = &key[0..depth];
+        let index = key[depth];
+        steps.push(BranchStep {
+            path: path.to_vec(),
+            index,
+            meta: storage.get_branch_meta(path).unwrap(),
+        });
+
+        match storage.get_branch_child(path, index) {
+            None => break PathEnd::Empty,
+            Some(BranchChild::Branch(_)) => depth += 1,
+            Some(BranchChild::Stem(other)) => {
+                let meta = storage.get_stem_meta(other).unwrap();
+                if other == stem {
+                    break PathEnd::Stem {
+                        meta,
+                        c2: key[31] >= 128,
+                        value: storage.get_leaf(key),
+                    };
+                }
+                let shared_prefix = other
+                    .iter()
+                    .zip(stem.iter())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                break PathEnd::OtherStem {
+                    stem: other,
+                    shared_prefix,
+                    meta,
+                };
+            }
+        }
+    };
+
+    KeyExplanation { key, steps, end }
+}
+
+pub fn explain_key_hex<DB: ReadOnlyHigherDb>(
+    storage: &DB,
+    key: &str,
+) -> Result<KeyExplanation, CodecError> {
+    let key = parse_hex::<32>(key)?;
+    Ok(explain_key(storage, key))
+}
+
+// The trie of `proof::proof_of_absence_edge_case2`, plus a stem deep enough to add branches
+pub fn explain_key_scenarios() {
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    trie.insert_single([0; 32], [0; 32]);
+    let mut key_ff = [0; 32];
+    key_ff[31] = 0xff;
+    trie.insert_single(key_ff, [0; 32]);
+
+    let mut deep = [0; 32];
+    deep[2] = 1;
+    trie.insert_single(deep, [1; 32]);
+
+    let explanation = explain_key(&trie.storage, [0; 32]);
+    println!("{}\n", explanation);
+    assert_eq!(explanation.value(), Some([0; 32]));
+    assert_eq!(explanation.steps.len(), 3);
+
+    // Same stem, suffix in C1 that was never written
+    let mut key_unset = [0; 32];
+    key_unset[31] = 1;
+    let explanation = explain_key(&trie.storage, key_unset);
+    println!("{}\n", explanation);
+    assert!(matches!(
+        explanation.end,
+        PathEnd::Stem {
+            c2: false,
+            value: None,
+            ..
+        }
+    ));
+
+    // The absent key of proof_of_absence_edge_case2 hits an empty slot at the root
+    let explanation = explain_key_hex(&trie.storage, &hex::encode([1u8; 32])).unwrap();
+    println!("{}\n", explanation);
+    assert_eq!(explanation.end, PathEnd::Empty);
+
+    // Lands on the stem of `deep`, which shares the first 30 bytes
+    let mut key_other = deep;
+    key_other[30] = 7;
+    let explanation = explain_key(&trie.storage, key_other);
+    println!("{}", explanation);
+    assert!(matches!(
+        explanation.end,
+        PathEnd::OtherStem {
+            shared_prefix: 30,
+            ..
+        }
+    ));
+}
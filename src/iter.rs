@@ -0,0 +1,199 @@
+use rand::Rng;
+use std::collections::{BTreeMap, VecDeque};
+use verkle_trie::{
+    database::{memory_db::MemoryDb, meta::BranchChild, ReadOnlyHigherDb},
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::workload::scenario_rng;
+
+enum Pending {
+    Branch(Vec<u8>),
+    Stem([u8; 31]),
+}
+
+// Walks the trie depth first, visiting children in index order, so the leaves
+// come out sorted by key. Stems outside of [start, end) are pruned on the way down.
+pub struct LeafIter<'a, DB> {
+    db: &'a DB,
+    stack: Vec<Pending>,
+    leaves: VecDeque<([u8; 32], [u8; 32])>,
+    start: [u8; 31],
+    end: Option<[u8; 31]>,
+}
+
+impl<'a, DB: ReadOnlyHigherDb> LeafIter<'a, DB> {
+    fn new(db: &'a DB, root: Pending, start: [u8; 31], end: Option<[u8; 31]>) -> Self {
+        LeafIter {
+            db,
+            stack: vec![root],
+            leaves: VecDeque::new(),
+            start,
+            end,
+        }
+    }
+
+    // Whether the subtree under `path` can hold a stem in [start, end)
+    fn overlaps(&self, path: &[u8]) -> bool {
+        let len = path.len();
+        if path < &self.start[..len] {
+            return false;
+        }
+        match &self.end {
+            Some(end) => path <= &end[..len],
+            None => true,
+        }
+    }
+
+    fn in_range(&self, stem: &[u8; 31]) -> bool {
+        *stem >= self.start && self.end.is_none_or(|end| *stem < end)
+    }
+}
+
+impl<'a, DB: ReadOnlyHigherDb> Iterator for LeafIter<'a, DB> {
+    type Item = ([u8; 32], [u8; 32]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(leaf) = self.leaves.pop_front() {
+                return Some(leaf);
+            }
+
+            match self.stack.pop()? {
+                Pending::Branch(path) => {
+                    let mut children = self.db.get_branch_children(&path);
+                    children.sort_by_key(|(index, _)| *index);
+                    for (index, child) in children.into_iter().rev() {
+                        let mut child_path = path.clone();
+                        child_path.push(index);
+                        if !self.overlaps(&child_path) {
+                            continue;
+                        }
+                        self.stack.push(match child {
+                            BranchChild::Branch(_) => Pending::Branch(child_path),
+                            BranchChild::Stem(stem) => Pending::Stem(stem),
+                        });
+                    }
+                }
+                Pending::Stem(stem) => {
+                    if !self.in_range(&stem) {
+                        continue;
+                    }
+                    let mut children = self.db.get_stem_children(stem);
+                    children.sort_by_key(|(suffix, _)| *suffix);
+                    self.leaves
+                        .extend(children.into_iter().map(|(suffix, value)| {
+                            let mut key = [0u8; 32];
+                            key[..31].copy_from_slice(&stem);
+                            key[31] = suffix;
+                            (key, value)
+                        }));
+                }
+            }
+        }
+    }
+}
+
+// Every (key, value) pair in key order
+pub fn iter_leaves<DB: ReadOnlyHigherDb>(db: &DB) -> LeafIter<'_, DB> {
+    LeafIter::new(db, Pending::Branch(vec![]), [0u8; 31], None)
+}
+
+// Every leaf whose stem is in [start, end)
+pub fn iter_stem_range<DB: ReadOnlyHigherDb>(
+    db: &DB,
+    start: [u8; 31],
+    end: [u8; 31],
+) -> LeafIter<'_, DB> {
+    LeafIter::new(db, Pending::Branch(vec![]), start, Some(end))
+}
+
+// Every leaf whose key starts with `prefix`.
+// Descends straight to the node covering the prefix instead of walking from the root.
+pub fn iter_prefix<'a, DB: ReadOnlyHigherDb>(
+    db: &'a DB,
+    prefix: &'a [u8],
+) -> impl Iterator<Item = ([u8; 32], [u8; 32])> + 'a {
+    assert!(prefix.len() <= 32);
+
+    let mut path = vec![];
+    let mut root = Some(Pending::Branch(vec![]));
+    while path.len() < prefix.len().min(31) {
+        match db.get_branch_child(&path, prefix[path.len()]) {
+            Some(BranchChild::Branch(_)) => {
+                path.push(prefix[path.len()]);
+                root = Some(Pending::Branch(path.clone()));
+            }
+            Some(BranchChild::Stem(stem)) => {
+                root = Some(Pending::Stem(stem));
+                break;
+            }
+            None => {
+                root = None;
+                break;
+            }
+        }
+    }
+
+    root.into_iter()
+        .flat_map(move |root| LeafIter::new(db, root, [0u8; 31], None))
+        .filter(move |(key, _)| key.starts_with(prefix))
+}
+
+pub fn iterate_in_key_order() {
+    let mut rng = scenario_rng("iterate_in_key_order");
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let mut expected = BTreeMap::new();
+
+    for i in 0..200u32 {
+        let mut key: [u8; 32] = rng.gen();
+        // Cluster some keys under the same first bytes and the same stem
+        if i % 3 == 0 {
+            key[..2].copy_from_slice(&[0xab, 0xcd]);
+        }
+        if i % 7 == 0 {
+            key[..31].copy_from_slice(&[0x11; 31]);
+        }
+        let value: [u8; 32] = rng.gen();
+        trie.insert_single(key, value);
+        expected.insert(key, value);
+    }
+
+    let all: Vec<_> = iter_leaves(&trie.storage).collect();
+    let sorted: Vec<_> = expected.iter().map(|(k, v)| (*k, *v)).collect();
+    assert_eq!(all, sorted);
+
+    for prefix in [
+        &[0xab][..],
+        &[0xab, 0xcd],
+        &[0x11; 31],
+        &[0x11; 32],
+        &[0x00, 0x01, 0x02],
+    ] {
+        let got: Vec<_> = iter_prefix(&trie.storage, prefix).collect();
+        let want: Vec<_> = sorted
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .copied()
+            .collect();
+        assert_eq!(got, want, "prefix {:?}", prefix);
+    }
+
+    let mut start = [0u8; 31];
+    start[0] = 0x40;
+    let mut end = [0u8; 31];
+    end[0] = 0xab;
+    end[1] = 0xce;
+    let got: Vec<_> = iter_stem_range(&trie.storage, start, end).collect();
+    let want: Vec<_> = sorted
+        .iter()
+        .filter(|(key, _)| key[..31] >= start[..] && key[..31] < end[..])
+        .copied()
+        .collect();
+    assert_eq!(got, want);
+
+    for (key, value) in &all {
+        assert_eq!(trie.get(*key), Some(*value));
+    }
+}
@@ -0,0 +1,217 @@
+use banderwagon::{trait_defs::*, Element, Fr};
+use std::fmt;
+use verkle_trie::{
+    constants::TWO_POW_128,
+    database::{memory_db::MemoryDb, ReadOnlyHigherDb},
+    proof::{prover, VerkleProof},
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::codec::fr_to_le_bytes;
+
+// A leaf slot in a stem. A stored zero is `Present([0; 32])`, which commits to
+// (2^128, 0), while an absent leaf commits to (0, 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LeafValue {
+    Absent,
+    Present([u8; 32]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafError {
+    // value_low is neither 0 nor 2^128 plus a 16 byte integer
+    MissingMarker,
+    // value_high does not fit in 16 bytes
+    HighOutOfRange,
+    // value_low is unset but value_high is not, which no leaf encodes to
+    AbsentWithHigh,
+}
+
+impl fmt::Display for LeafError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeafError::MissingMarker => write!(f, "value_low does not carry the 2^128 marker"),
+            LeafError::HighOutOfRange => write!(f, "value_high is larger than 2^128"),
+            LeafError::AbsentWithHigh => write!(f, "value_high is set for an absent leaf"),
+        }
+    }
+}
+
+impl std::error::Error for LeafError {}
+
+// (value_low + 2^128, value_high), with the 32 bytes split into little endian halves
+pub fn split_value(value: &[u8; 32]) -> (Fr, Fr) {
+    let value_low = Fr::from_le_bytes_mod_order(&value[0..16]) + TWO_POW_128;
+    let value_high = Fr::from_le_bytes_mod_order(&value[16..32]);
+    (value_low, value_high)
+}
+
+fn low_half(scalar: &Fr) -> Option<[u8; 16]> {
+    let bytes = fr_to_le_bytes(scalar);
+    if bytes[16..].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(bytes[0..16].try_into().unwrap())
+}
+
+// Inverse of `split_value`
+pub fn merge_value(value_low: Fr, value_high: Fr) -> Result<[u8; 32], LeafError> {
+    let low = low_half(&(value_low - TWO_POW_128)).ok_or(LeafError::MissingMarker)?;
+    let high = low_half(&value_high).ok_or(LeafError::HighOutOfRange)?;
+
+    let mut value = [0u8; 32];
+    value[0..16].copy_from_slice(&low);
+    value[16..32].copy_from_slice(&high);
+    Ok(value)
+}
+
+impl LeafValue {
+    pub fn is_present(&self) -> bool {
+        matches!(self, LeafValue::Present(_))
+    }
+
+    pub fn value(&self) -> Option<[u8; 32]> {
+        match self {
+            LeafValue::Absent => None,
+            LeafValue::Present(value) => Some(*value),
+        }
+    }
+
+    // The two evaluations at 2 * (suffix % 128) and the one after in C1 or C2
+    pub fn to_scalars(&self) -> (Fr, Fr) {
+        match self {
+            LeafValue::Absent => (Fr::zero(), Fr::zero()),
+            LeafValue::Present(value) => split_value(value),
+        }
+    }
+
+    pub fn from_scalars(value_low: Fr, value_high: Fr) -> Result<Self, LeafError> {
+        if value_low.is_zero() {
+            if !value_high.is_zero() {
+                return Err(LeafError::AbsentWithHigh);
+            }
+            return Ok(LeafValue::Absent);
+        }
+        merge_value(value_low, value_high).map(LeafValue::Present)
+    }
+}
+
+impl From<Option<[u8; 32]>> for LeafValue {
+    fn from(value: Option<[u8; 32]>) -> Self {
+        value.map_or(LeafValue::Absent, LeafValue::Present)
+    }
+}
+
+impl From<LeafValue> for Option<[u8; 32]> {
+    fn from(value: LeafValue) -> Self {
+        value.value()
+    }
+}
+
+impl fmt::Display for LeafValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LeafValue::Absent => write!(f, "absent"),
+            LeafValue::Present(value) => write!(f, "present {}", hex::encode(value)),
+        }
+    }
+}
+
+pub fn get_leaf<DB: ReadOnlyHigherDb>(storage: &DB, key: [u8; 32]) -> LeafValue {
+    storage.get_leaf(key).into()
+}
+
+// A proof for the keys along with what it proves for each of them
+pub fn prove_leaves<DB: ReadOnlyHigherDb>(
+    storage: &DB,
+    keys: Vec<[u8; 32]>,
+) -> (VerkleProof, Vec<LeafValue>) {
+    let values = keys.iter().map(|key| get_leaf(storage, *key)).collect();
+    let proof = prover::create_verkle_proof(storage, keys).unwrap();
+    (proof, values)
+}
+
+pub fn verify_leaves(
+    proof: VerkleProof,
+    keys: Vec<[u8; 32]>,
+    values: &[LeafValue],
+    root: Element,
+) -> bool {
+    let values = values.iter().map(|value| value.value()).collect();
+    let (ok, _) = proof.check(keys, values, root);
+    ok
+}
+
+pub fn leaf_encoding_checks() {
+    // Round trips, including the all zero and all 0xff values
+    let mut values = vec![[0u8; 32], [0xffu8; 32]];
+    let mut counting = [0u8; 32];
+    for (i, byte) in counting.iter_mut().enumerate() {
+        *byte = i as u8 + 1;
+    }
+    values.push(counting);
+    for value in &values {
+        let (low, high) = split_value(value);
+        assert_eq!(merge_value(low, high), Ok(*value));
+        assert_eq!(
+            LeafValue::from_scalars(low, high),
+            Ok(LeafValue::Present(*value))
+        );
+    }
+
+    // A stored zero and an absent leaf encode differently
+    assert_eq!(
+        LeafValue::Present([0; 32]).to_scalars(),
+        (TWO_POW_128, Fr::zero())
+    );
+    assert_eq!(LeafValue::Absent.to_scalars(), (Fr::zero(), Fr::zero()));
+    assert_eq!(
+        LeafValue::from_scalars(Fr::zero(), Fr::zero()),
+        Ok(LeafValue::Absent)
+    );
+
+    // Scalars no value encodes to
+    assert_eq!(
+        LeafValue::from_scalars(Fr::from(1u64), Fr::zero()),
+        Err(LeafError::MissingMarker)
+    );
+    assert_eq!(
+        LeafValue::from_scalars(TWO_POW_128, TWO_POW_128),
+        Err(LeafError::HighOutOfRange)
+    );
+    assert_eq!(
+        LeafValue::from_scalars(Fr::zero(), Fr::from(1u64)),
+        Err(LeafError::AbsentWithHigh)
+    );
+
+    // Gets and proofs keep the distinction
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let zero_key = [0u8; 32];
+    trie.insert_single(zero_key, [0; 32]);
+    let mut unset_key = zero_key;
+    unset_key[31] = 1;
+    let absent_stem_key = [1u8; 32];
+
+    let keys = vec![zero_key, unset_key, absent_stem_key];
+    let (proof, proven) = prove_leaves(&trie.storage, keys.clone());
+    assert_eq!(
+        proven,
+        vec![
+            LeafValue::Present([0; 32]),
+            LeafValue::Absent,
+            LeafValue::Absent
+        ]
+    );
+
+    let root = trie.root_commitment();
+    assert!(verify_leaves(proof.clone(), keys.clone(), &proven, root));
+
+    // Claiming the stored zero is absent, or the absent leaf is zero, fails
+    let swapped = vec![
+        LeafValue::Absent,
+        LeafValue::Present([0; 32]),
+        LeafValue::Absent,
+    ];
+    assert!(!verify_leaves(proof, keys, &swapped, root));
+}
@@ -0,0 +1,25 @@
+pub mod abel_test;
+pub mod batch;
+pub mod bulk;
+pub mod cache_db;
+pub mod canonical;
+pub mod checkpoint;
+pub mod codec;
+pub mod counting_db;
+pub mod file_db;
+pub mod genesis;
+pub mod incremental;
+pub mod inspect;
+pub mod iter;
+pub mod leaf;
+pub mod naive;
+pub mod parallel;
+pub mod proof;
+pub mod prop_test;
+pub mod snapshot;
+pub mod trace;
+pub mod transition;
+pub mod trie;
+pub mod versioned;
+pub mod wnaf;
+pub mod workload;
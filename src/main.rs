@@ -1,18 +1,33 @@
-use ark_serialize::CanonicalSerialize;
-use ark_serialize::SerializationError;
-use banderwagon::{Element, Fr, PrimeField};
-use ipa_multipoint::committer::Committer;
-use verkle_trie::constants::TWO_POW_128;
-use verkle_trie::database::ReadOnlyHigherDb;
-use verkle_trie::group_to_field;
-
-pub mod abel_test;
-pub mod proof;
-pub mod trie;
+use verkle_tree_example::*;
 
 fn main() {
     proof::basic_proof();
     // trie::test_hash()
+    // prop_test::insertion_order_independence();
+    // naive::differential_random_workloads();
+    // incremental::incremental_matches_insert_single();
+    // canonical::check_canonical_encoding();
+    // codec::check_codec_round_trips();
+    // wnaf::wnaf_experiments();
+    // file_db::file_db_survives_restart();
+    // cache_db::cached_repeated_proofs();
+    // counting_db::count_insert_accesses();
+    // counting_db::count_proof_accesses();
+    // iter::iterate_in_key_order();
+    // batch::batch_matches_sequential();
+    // bulk::bulk_load_matches_trie();
+    // parallel::parallel_matches_sequential();
+    // genesis::import_small_genesis();
+    // genesis::print_genesis_root("genesis.json");
+    // transition::simulate_transition();
+    // versioned::historical_reads_and_proofs();
+    // checkpoint::checkpoint_and_rollback();
+    // snapshot::snapshots_share_structure();
+    // inspect::inspect_trie_scenarios();
+    // trace::trace_insert_scenarios();
+    // inspect::explain_key_scenarios();
+    // leaf::leaf_encoding_checks();
+    // workload::workloads_are_reproducible();
     // proof::proof_of_absence_edge_case2();
 }
 
@@ -67,19 +82,19 @@ fn main() {
 
 //     println!(
 //         "New stem commitment: {:?}",
-//         hex::encode(compress_point_to_array(&new_stem_commit).unwrap())
+//         codec::format_hex(&codec::element_to_compressed(&new_stem_commit))
 //     );
 //     println!(
 //         "New stem hash: {:?}",
-//         hex::encode(scalar_to_array(&new_stem_hash).unwrap())
+//         codec::format_hex(&codec::fr_to_le_bytes(&new_stem_hash))
 //     );
 //     println!(
 //         "new c_1 commitment: {:?}",
-//         hex::encode(compress_point_to_array(&c_1).unwrap())
+//         codec::format_hex(&codec::element_to_compressed(&c_1))
 //     );
 //     println!(
 //         "new c_1 hash: {:?}",
-//         hex::encode(scalar_to_array(&h_c_1).unwrap())
+//         codec::format_hex(&codec::fr_to_le_bytes(&h_c_1))
 //     );
 
 //     let stem_key_ff = [
@@ -109,28 +124,14 @@ fn main() {
 //     let get_branch = old_trie.storage.get_branch_meta(&branch_id).unwrap();
 //     println!(
 //         "New branch commitment: {:?}",
-//         hex::encode(compress_point_to_array(&branch_commit).unwrap())
+//         codec::format_hex(&codec::element_to_compressed(&branch_commit))
 //     );
 //     println!(
 //         "New branch hash: {:?}",
-//         hex::encode(scalar_to_array(&branch_hash).unwrap())
+//         codec::format_hex(&codec::fr_to_le_bytes(&branch_hash))
 //     );
 //     println!("branch meta: {:?}", get_branch);
 //     assert_eq!(branch_commit, get_branch.commitment);
 
 //     abel_test::check_update_bytes();
 // }
-
-fn scalar_to_array(scalar: &Fr) -> Result<[u8; 32], SerializationError> {
-    let mut bytes = [0u8; 32];
-    scalar.serialize_uncompressed(&mut bytes[..])?;
-
-    Ok(bytes)
-}
-
-fn compress_point_to_array(p: &Element) -> Result<[u8; 32], SerializationError> {
-    let mut bytes = [0u8; 32];
-    p.serialize_compressed(&mut bytes[..])?;
-
-    Ok(bytes)
-}
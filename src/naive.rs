@@ -0,0 +1,240 @@
+use banderwagon::{trait_defs::*, Element, Fr};
+use std::collections::BTreeMap;
+use std::ops::Mul;
+use verkle_trie::{
+    constants::{CRS, TWO_POW_128},
+    database::{memory_db::MemoryDb, ReadOnlyHigherDb},
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::workload::{Distribution, Workload};
+
+// A deliberately slow verkle trie that follows the spec literally:
+// every commitment is recomputed from scratch as an MSM over the CRS,
+// and there is no database or caching involved.
+// It is used as an oracle for `verkle_trie::trie::Trie`, so it shares no code
+// with the other writers, not even the leaf value encoding.
+#[derive(Debug, Clone)]
+pub enum Node {
+    Stem {
+        stem: [u8; 31],
+        values: BTreeMap<u8, [u8; 32]>,
+    },
+    Branch(BTreeMap<u8, Node>),
+}
+
+#[derive(Debug, Clone)]
+pub struct NaiveTrie {
+    root: Node,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NaiveStemCommitments {
+    pub c_1: Element,
+    pub c_2: Element,
+    pub stem_commitment: Element,
+}
+
+impl Default for NaiveTrie {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NaiveTrie {
+    pub fn new() -> NaiveTrie {
+        NaiveTrie {
+            root: Node::Branch(BTreeMap::new()),
+        }
+    }
+
+    pub fn insert(&mut self, key: [u8; 32], value: [u8; 32]) {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        Self::insert_at(&mut self.root, 0, stem, key[31], value);
+    }
+
+    fn insert_at(node: &mut Node, depth: usize, stem: [u8; 31], suffix: u8, value: [u8; 32]) {
+        match node {
+            Node::Stem {
+                stem: existing,
+                values,
+            } if *existing == stem => {
+                values.insert(suffix, value);
+            }
+            Node::Stem { .. } => {
+                // Two different stems meet, so the existing stem is pushed one level down
+                // and we retry the insert against the new branch
+                let existing = std::mem::replace(node, Node::Branch(BTreeMap::new()));
+                let existing_index = match &existing {
+                    Node::Stem { stem, .. } => stem[depth],
+                    Node::Branch(_) => unreachable!(),
+                };
+                if let Node::Branch(children) = node {
+                    children.insert(existing_index, existing);
+                }
+                Self::insert_at(node, depth, stem, suffix, value);
+            }
+            Node::Branch(children) => match children.get_mut(&stem[depth]) {
+                Some(child) => Self::insert_at(child, depth + 1, stem, suffix, value),
+                None => {
+                    let mut values = BTreeMap::new();
+                    values.insert(suffix, value);
+                    children.insert(stem[depth], Node::Stem { stem, values });
+                }
+            },
+        }
+    }
+
+    pub fn get(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        let mut node = &self.root;
+        for depth in 0..31 {
+            match node {
+                Node::Stem { stem, values } => {
+                    if stem[..] != key[0..31] {
+                        return None;
+                    }
+                    return values.get(&key[31]).copied();
+                }
+                Node::Branch(children) => node = children.get(&key[depth])?,
+            }
+        }
+        match node {
+            Node::Stem { stem, values } if stem[..] == key[0..31] => values.get(&key[31]).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn root_commitment(&self) -> Element {
+        commit(&self.root)
+    }
+
+    pub fn root_hash(&self) -> Fr {
+        group_to_field(&self.root_commitment())
+    }
+
+    // Every stem in the trie along with its freshly computed commitments
+    pub fn stem_commitments(&self) -> BTreeMap<[u8; 31], NaiveStemCommitments> {
+        let mut res = BTreeMap::new();
+        collect_stems(&self.root, &mut res);
+        res
+    }
+}
+
+fn collect_stems(node: &Node, res: &mut BTreeMap<[u8; 31], NaiveStemCommitments>) {
+    match node {
+        Node::Stem { stem, values } => {
+            res.insert(*stem, stem_commitments(stem, values));
+        }
+        Node::Branch(children) => children
+            .values()
+            .for_each(|child| collect_stems(child, res)),
+    }
+}
+
+// sum of scalar_i * G_i
+fn msm(scalars: &[(usize, Fr)]) -> Element {
+    scalars
+        .iter()
+        .fold(Element::zero(), |acc, (index, scalar)| {
+            acc + CRS[*index].mul(*scalar)
+        })
+}
+
+fn commit(node: &Node) -> Element {
+    match node {
+        Node::Stem { stem, values } => stem_commitments(stem, values).stem_commitment,
+        Node::Branch(children) => {
+            let scalars: Vec<_> = children
+                .iter()
+                .map(|(index, child)| (*index as usize, group_to_field(&commit(child))))
+                .collect();
+            msm(&scalars)
+        }
+    }
+}
+
+fn stem_commitments(stem: &[u8; 31], values: &BTreeMap<u8, [u8; 32]>) -> NaiveStemCommitments {
+    let mut c_1_scalars = Vec::new();
+    let mut c_2_scalars = Vec::new();
+
+    for (suffix, value) in values {
+        // value_low gets 2^128 added, so that a stored zero differs from an absent leaf
+        let value_low = Fr::from_le_bytes_mod_order(&value[0..16]) + TWO_POW_128;
+        let value_high = Fr::from_le_bytes_mod_order(&value[16..32]);
+
+        let (scalars, offset) = if *suffix < 128 {
+            (&mut c_1_scalars, *suffix as usize)
+        } else {
+            (&mut c_2_scalars, *suffix as usize - 128)
+        };
+        scalars.push((2 * offset, value_low));
+        scalars.push((2 * offset + 1, value_high));
+    }
+
+    let c_1 = msm(&c_1_scalars);
+    let c_2 = msm(&c_2_scalars);
+
+    // 1 * G_0 + stem * G_1 + group_to_field(C1) * G_2 + group_to_field(C2) * G_3
+    let stem_commitment = msm(&[
+        (0, Fr::one()),
+        (1, Fr::from_le_bytes_mod_order(stem)),
+        (2, group_to_field(&c_1)),
+        (3, group_to_field(&c_2)),
+    ]);
+
+    NaiveStemCommitments {
+        c_1,
+        c_2,
+        stem_commitment,
+    }
+}
+
+// Every workload is seeded, so a mismatch can be replayed from the printed label
+pub fn differential_random_workloads() {
+    let distributions = [
+        Distribution::Uniform,
+        Distribution::ClusteredStems { stems: 4 },
+        Distribution::AccountLike { slots: 80 },
+        Distribution::SharedPrefix { prefix_len: 29 },
+        Distribution::MixedPrefixes,
+    ];
+    let mut workloads = Vec::new();
+    for n in [0, 1, 2, 16, 64, 256] {
+        for distribution in distributions {
+            let label = format!("{:?}, seed {}", distribution, n);
+            workloads.push((label, Workload::new(n as u64, distribution).kvs(n)));
+        }
+    }
+
+    for (label, kvs) in workloads {
+        let n = kvs.len();
+
+        let mut naive = NaiveTrie::new();
+        let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+        for (key, value) in &kvs {
+            naive.insert(*key, *value);
+            trie.insert_single(*key, *value);
+        }
+
+        assert_eq!(naive.root_commitment(), trie.root_commitment(), "{}", label);
+        assert_eq!(naive.root_hash(), trie.root_hash(), "{}", label);
+
+        for (stem, expected) in naive.stem_commitments() {
+            let meta = trie.storage.get_stem_meta(stem).unwrap();
+            assert_eq!(meta.c_1, expected.c_1, "c_1 mismatch for stem {:?}", stem);
+            assert_eq!(meta.c_2, expected.c_2, "c_2 mismatch for stem {:?}", stem);
+            assert_eq!(
+                meta.stem_commitment, expected.stem_commitment,
+                "stem commitment mismatch for stem {:?}",
+                stem
+            );
+        }
+
+        for (key, _) in &kvs {
+            assert_eq!(naive.get(*key), trie.get(*key));
+        }
+        println!("{} ({} keys): root {:?}", label, n, naive.root_hash());
+    }
+}
@@ -0,0 +1,291 @@
+use banderwagon::Fr;
+use ipa_multipoint::committer::Committer;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+use verkle_trie::{
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::batch::{
+    apply_writes, fresh_stem_meta, group_by_stem, updated_stem_meta, write_leaves, BranchUpdate,
+    NodeWrite,
+};
+use crate::bulk::{self, KeyValue};
+use crate::workload::{scenario_seed, Distribution, Workload};
+
+// Sibling subtrees are handed to the thread pool down to this depth,
+// below that a subtree is small enough to be built on a single thread
+const PARALLEL_DEPTH: usize = 2;
+
+// Every thread collects the writes of its own subtree. They are concatenated
+// in child index order, so the database sees the same writes in the same order
+// no matter how the work was scheduled.
+fn join_children(
+    results: Vec<((Fr, usize), Vec<NodeWrite>)>,
+) -> (Vec<(Fr, usize)>, Vec<NodeWrite>) {
+    let mut children = Vec::with_capacity(results.len());
+    let mut writes = Vec::new();
+    for (child, child_writes) in results {
+        children.push(child);
+        writes.extend(child_writes);
+    }
+    (children, writes)
+}
+
+// Parallel version of `bulk::bulk_load` for key/values that are already in memory.
+// Returns the root hash.
+pub fn par_bulk_load<DB, C>(storage: &mut DB, committer: &C, kvs: &[KeyValue]) -> Fr
+where
+    DB: WriteOnlyHigherDb,
+    C: Committer + Sync,
+{
+    let writes = group_by_stem(kvs.iter().copied());
+    let stems: Vec<([u8; 31], StemMeta)> = writes
+        .par_iter()
+        .map(|(stem, leaves)| (*stem, fresh_stem_meta(committer, stem, leaves)))
+        .collect();
+
+    let hashes: Vec<([u8; 31], Fr)> = stems
+        .iter()
+        .map(|(stem, meta)| (*stem, meta.hash_stem_commitment))
+        .collect();
+    let mut node_writes = Vec::new();
+    let root = par_build_branch(committer, vec![], &hashes, &mut node_writes);
+
+    // The branch build only places the stems, their metas go at the depth they were placed at
+    let depths: HashMap<[u8; 31], u8> = node_writes
+        .iter()
+        .filter_map(|write| match write {
+            NodeWrite::StemChild(_, stem, depth) => Some((*stem, *depth)),
+            _ => None,
+        })
+        .collect();
+    node_writes.extend(
+        stems
+            .into_iter()
+            .map(|(stem, meta)| NodeWrite::Stem(stem, meta, depths[&stem])),
+    );
+
+    write_leaves(storage, &writes, &node_writes);
+    apply_writes(storage, node_writes);
+    root.hash_commitment
+}
+
+fn par_build_branch<C: Committer + Sync>(
+    committer: &C,
+    path: Vec<u8>,
+    stems: &[([u8; 31], Fr)],
+    writes: &mut Vec<NodeWrite>,
+) -> BranchMeta {
+    if path.len() >= PARALLEL_DEPTH {
+        return bulk::build_branch(committer, path, stems, writes);
+    }
+
+    let groups: Vec<_> = stems
+        .chunk_by(|a, b| a.0[path.len()] == b.0[path.len()])
+        .collect();
+    let results = groups
+        .into_par_iter()
+        .map(|group| {
+            let mut child_writes = Vec::new();
+            let child = match group {
+                [_] => bulk::build_child(committer, &path, group, &mut child_writes),
+                _ => {
+                    let index = group[0].0[path.len()];
+                    let mut child_path = path.clone();
+                    child_path.push(index);
+                    let meta = par_build_branch(committer, child_path, group, &mut child_writes);
+                    (meta.hash_commitment, index as usize)
+                }
+            };
+            (child, child_writes)
+        })
+        .collect();
+
+    let (children, child_writes) = join_children(results);
+    writes.extend(child_writes);
+    bulk::finish_branch(committer, path, children, writes)
+}
+
+// Parallel version of `batch::batch_insert`. Returns the new root hash.
+pub fn par_batch_insert<DB, C>(
+    trie: &mut Trie<DB, C>,
+    kvs: impl IntoIterator<Item = KeyValue>,
+) -> Fr
+where
+    DB: ReadOnlyHigherDb + WriteOnlyHigherDb + Sync,
+    C: Committer + Sync,
+{
+    let writes = group_by_stem(kvs);
+    let storage = &trie.storage;
+    let committer = &trie.committer;
+    let stem_metas = writes
+        .par_iter()
+        .map(|(stem, leaves)| (*stem, updated_stem_meta(storage, committer, stem, leaves)))
+        .collect();
+
+    let stems: Vec<[u8; 31]> = writes.keys().copied().collect();
+    let update = BranchUpdate {
+        storage,
+        committer,
+        stem_metas,
+    };
+    let mut node_writes = Vec::new();
+    let root = par_update_branch(&update, vec![], &stems, &mut node_writes);
+
+    write_leaves(&mut trie.storage, &writes, &node_writes);
+    apply_writes(&mut trie.storage, node_writes);
+    root.hash_commitment
+}
+
+fn par_update_branch<DB, C>(
+    update: &BranchUpdate<'_, DB, C>,
+    path: Vec<u8>,
+    stems: &[[u8; 31]],
+    writes: &mut Vec<NodeWrite>,
+) -> BranchMeta
+where
+    DB: ReadOnlyHigherDb + Sync,
+    C: Committer + Sync,
+{
+    if path.len() >= PARALLEL_DEPTH {
+        return update.update_branch(path, stems, writes);
+    }
+
+    let groups: Vec<_> = stems
+        .chunk_by(|a, b| a[path.len()] == b[path.len()])
+        .collect();
+    let results = groups
+        .into_par_iter()
+        .map(|group| {
+            let mut child_writes = Vec::new();
+            let index = group[0][path.len()];
+            let child = match update.storage.get_branch_child(&path, index) {
+                // Only existing branches are recursed into in parallel, every other case
+                // changes the shape of the trie and is left to the sequential update
+                Some(BranchChild::Branch(old)) => {
+                    let mut child_path = path.clone();
+                    child_path.push(index);
+                    let meta = par_update_branch(update, child_path, group, &mut child_writes);
+                    (meta.hash_commitment - old.hash_commitment, index as usize)
+                }
+                _ => update.update_child(&path, group, &mut child_writes),
+            };
+            (child, child_writes)
+        })
+        .collect();
+
+    let (deltas, child_writes) = join_children(results);
+    writes.extend(child_writes);
+    update.finish_branch(path, deltas, writes)
+}
+
+pub fn parallel_matches_sequential() {
+    let seed = scenario_seed("parallel_matches_sequential");
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut kvs = Workload::new(seed, Distribution::Uniform).kvs(20_000);
+    kvs.sort();
+    kvs.dedup_by_key(|(key, _)| *key);
+
+    let mut sequential = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let start = Instant::now();
+    let root = bulk::bulk_load(
+        &mut sequential.storage,
+        &sequential.committer,
+        kvs.iter().copied().map(Ok),
+    )
+    .unwrap()
+    .root;
+    println!("bulk load: {:?}", start.elapsed());
+
+    let mut parallel = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let start = Instant::now();
+    let par_root = par_bulk_load(&mut parallel.storage, &parallel.committer, &kvs);
+    println!("parallel bulk load: {:?}", start.elapsed());
+
+    assert_eq!(par_root, root);
+    assert_eq!(parallel.root_commitment(), sequential.root_commitment());
+
+    // Updates on top: overwrites, new leaves in existing stems and new stems
+    let updates: Vec<KeyValue> = (0..5_000)
+        .map(|i| {
+            let mut key = kvs[rng.gen_range(0..kvs.len())].0;
+            match i % 3 {
+                0 => {}
+                1 => key[31] = rng.gen(),
+                _ => {
+                    let from = rng.gen_range(1..31);
+                    let tail: [u8; 32] = rng.gen();
+                    key[from..].copy_from_slice(&tail[from..]);
+                }
+            }
+            (key, rng.gen())
+        })
+        .collect();
+
+    let start = Instant::now();
+    let root = crate::batch::batch_insert(&mut sequential, updates.iter().copied());
+    println!("batch insert: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let par_root = par_batch_insert(&mut parallel, updates.iter().copied());
+    println!("parallel batch insert: {:?}", start.elapsed());
+
+    assert_eq!(par_root, root);
+    assert_eq!(parallel.root_commitment(), sequential.root_commitment());
+    for (key, _) in &updates {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        assert_eq!(
+            parallel.storage.get_stem_meta(stem),
+            sequential.storage.get_stem_meta(stem)
+        );
+    }
+
+    // And the same as the single-threaded Trie, node for node
+    let mut reference = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    let start = Instant::now();
+    for (key, value) in kvs.iter().chain(&updates) {
+        reference.insert_single(*key, *value);
+    }
+    println!("insert_single: {:?}", start.elapsed());
+
+    assert_eq!(par_root, reference.root_hash());
+    assert_eq!(parallel.root_commitment(), reference.root_commitment());
+    for (key, _) in kvs.iter().chain(&updates) {
+        let stem: [u8; 31] = key[0..31].try_into().unwrap();
+        assert_eq!(
+            parallel.storage.get_stem_meta(stem),
+            reference.storage.get_stem_meta(stem)
+        );
+        assert_eq!(parallel.get(*key), reference.get(*key));
+    }
+    assert_eq!(
+        parallel.storage.branch_table.len(),
+        reference.storage.branch_table.len()
+    );
+    for (path, child) in &reference.storage.branch_table {
+        match (child, parallel.storage.branch_table.get(path)) {
+            (BranchChild::Branch(expected), Some(BranchChild::Branch(meta))) => {
+                assert_eq!(meta, expected, "branch {:?}", path)
+            }
+            (BranchChild::Stem(expected), Some(BranchChild::Stem(stem))) => {
+                assert_eq!(stem, expected, "stem child {:?}", path)
+            }
+            _ => panic!("node {:?} differs from insert_single", path),
+        }
+    }
+
+    // The parallel result is deterministic
+    let mut again = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    par_bulk_load(&mut again.storage, &again.committer, &kvs);
+    par_batch_insert(&mut again, updates.iter().copied());
+    assert_eq!(again.root_commitment(), parallel.root_commitment());
+}
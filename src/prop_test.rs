@@ -0,0 +1,97 @@
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestRunner};
+use std::collections::HashMap;
+use verkle_trie::{
+    database::{memory_db::MemoryDb, ReadOnlyHigherDb},
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+type Writes = Vec<([u8; 32], [u8; 32])>;
+
+// Random keys which share the first `shared` bytes, so that depending on the case
+// the stems land in the same branch, the same stem or spread out from the root
+fn shared_prefix_writes() -> impl Strategy<Value = Writes> {
+    (
+        any::<[u8; 32]>(),
+        0..=31usize,
+        prop::collection::vec((any::<[u8; 32]>(), any::<[u8; 32]>()), 1..16),
+    )
+        .prop_map(|(prefix, shared, kvs)| {
+            kvs.into_iter()
+                .map(|(mut key, value)| {
+                    key[..shared].copy_from_slice(&prefix[..shared]);
+                    (key, value)
+                })
+                .collect()
+        })
+}
+
+// Appends overwrites of already written keys to the write sequence
+fn writes_with_overwrites() -> impl Strategy<Value = Writes> {
+    (
+        shared_prefix_writes(),
+        prop::collection::vec((any::<prop::sample::Index>(), any::<[u8; 32]>()), 0..8),
+    )
+        .prop_map(|(mut writes, overwrites)| {
+            for (index, value) in overwrites {
+                let key = writes[index.index(writes.len())].0;
+                writes.push((key, value));
+            }
+            writes
+        })
+}
+
+// The final state is the last value written for each key
+fn last_writes(writes: &Writes) -> Writes {
+    let mut order = Vec::new();
+    let mut last = HashMap::new();
+    for (key, value) in writes {
+        if last.insert(*key, *value).is_none() {
+            order.push(*key);
+        }
+    }
+    order.into_iter().map(|key| (key, last[&key])).collect()
+}
+
+pub fn insertion_order_independence() {
+    let strategy = writes_with_overwrites().prop_flat_map(|writes| {
+        let finals = last_writes(&writes);
+        (Just(writes), Just(finals).prop_shuffle())
+    });
+
+    let mut runner = TestRunner::new(Config {
+        cases: 16,
+        ..Config::default()
+    });
+
+    runner
+        .run(&strategy, |(writes, shuffled)| {
+            let mut sequential = Trie::new(DefaultConfig::new(MemoryDb::new()));
+            for (key, value) in &writes {
+                sequential.insert_single(*key, *value);
+            }
+
+            let mut permuted = Trie::new(DefaultConfig::new(MemoryDb::new()));
+            for (key, value) in &shuffled {
+                permuted.insert_single(*key, *value);
+            }
+
+            prop_assert_eq!(sequential.root_hash(), permuted.root_hash());
+
+            for (key, value) in &shuffled {
+                let stem: [u8; 31] = key[0..31].try_into().unwrap();
+                prop_assert_eq!(
+                    sequential.storage.get_stem_meta(stem),
+                    permuted.storage.get_stem_meta(stem)
+                );
+
+                // The last write to a key wins, regardless of how many overwrites happened
+                prop_assert_eq!(sequential.get(*key), Some(*value));
+                prop_assert_eq!(permuted.get(*key), Some(*value));
+            }
+
+            Ok(())
+        })
+        .unwrap();
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use verkle_trie::{
+    config::Config,
+    database::{
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    proof::prover,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+#[derive(Debug, Default)]
+struct Tables {
+    leaves: HashMap<[u8; 32], [u8; 32]>,
+    stems: HashMap<[u8; 31], StemMeta>,
+    branches: HashMap<Vec<u8>, BranchChild>,
+}
+
+impl Tables {
+    fn is_empty(&self) -> bool {
+        self.leaves.is_empty() && self.stems.is_empty() && self.branches.is_empty()
+    }
+
+    fn overwrite_with(&mut self, newer: Tables) {
+        self.leaves.extend(newer.leaves);
+        self.stems.extend(newer.stems);
+        self.branches.extend(newer.branches);
+    }
+}
+
+// An immutable set of writes on top of its parent
+#[derive(Debug)]
+struct Layer {
+    tables: Tables,
+    parent: Option<Arc<Layer>>,
+}
+
+// A database made of a chain of frozen layers shared with its snapshots, plus
+// the writes made since the last snapshot.
+//
+// Taking a snapshot freezes the pending writes into a layer without copying the state,
+// and both the database and the snapshot continue from that layer.
+// Later writes only copy the entries they touch into the pending writes,
+// so the snapshot never sees them. Layers that no snapshot holds any more are
+// merged into their parent, so the chain is only as long as the live snapshots need.
+#[derive(Debug, Default)]
+pub struct SnapshotDb {
+    frozen: Option<Arc<Layer>>,
+    pending: Tables,
+}
+
+impl SnapshotDb {
+    pub fn new() -> SnapshotDb {
+        SnapshotDb::default()
+    }
+
+    // A snapshot is a `SnapshotDb` itself, so it can be read, proven against,
+    // and even written to as an independent fork
+    pub fn snapshot(&mut self) -> SnapshotDb {
+        self.collapse();
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            match self.frozen.as_mut().and_then(Arc::get_mut) {
+                // No snapshot sees the top layer, so the writes can go straight into it
+                Some(top) => top.tables.overwrite_with(pending),
+                None => {
+                    self.frozen = Some(Arc::new(Layer {
+                        tables: pending,
+                        parent: self.frozen.take(),
+                    }))
+                }
+            }
+        }
+        SnapshotDb {
+            frozen: self.frozen.clone(),
+            pending: Tables::default(),
+        }
+    }
+
+    // Merges the top layer into its parent for as long as nothing else holds either
+    // of them. The cost is the size of the merged layer, which is what was written
+    // between the two snapshots.
+    fn collapse(&mut self) {
+        let Some(mut top) = self.frozen.take() else {
+            return;
+        };
+        while Arc::get_mut(&mut top)
+            .and_then(|layer| layer.parent.as_mut())
+            .and_then(Arc::get_mut)
+            .is_some()
+        {
+            let layer = Arc::try_unwrap(top).expect("checked to be unique");
+            let mut parent = layer.parent.expect("checked to have a parent");
+            Arc::get_mut(&mut parent)
+                .expect("checked to be unique")
+                .tables
+                .overwrite_with(layer.tables);
+            top = parent;
+        }
+        self.frozen = Some(top);
+    }
+
+    // The number of frozen layers a read may have to go through
+    pub fn layers(&self) -> usize {
+        let mut count = 0;
+        let mut layer = self.frozen.as_deref();
+        while let Some(current) = layer {
+            count += 1;
+            layer = current.parent.as_deref();
+        }
+        count
+    }
+
+    fn lookup<T>(&self, find: impl Fn(&Tables) -> Option<T>) -> Option<T> {
+        if let Some(found) = find(&self.pending) {
+            return Some(found);
+        }
+        let mut layer = self.frozen.as_deref();
+        while let Some(current) = layer {
+            if let Some(found) = find(&current.tables) {
+                return Some(found);
+            }
+            layer = current.parent.as_deref();
+        }
+        None
+    }
+}
+
+impl ReadOnlyHigherDb for SnapshotDb {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.lookup(|tables| tables.stems.get(&stem_key).copied())
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        (0..=255u8)
+            .filter_map(|index| {
+                self.get_branch_child(branch_id, index)
+                    .map(|child| (index, child))
+            })
+            .collect()
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        match self.lookup(|tables| tables.branches.get(key).cloned()) {
+            Some(BranchChild::Branch(meta)) => Some(meta),
+            _ => None,
+        }
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        let mut path = branch_id.to_vec();
+        path.push(index);
+        self.lookup(|tables| tables.branches.get(&path).cloned())
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        let mut key = [0u8; 32];
+        key[..31].copy_from_slice(&stem_key);
+        (0..=255u8)
+            .filter_map(|suffix| {
+                key[31] = suffix;
+                self.get_leaf(key).map(|value| (suffix, value))
+            })
+            .collect()
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.lookup(|tables| tables.leaves.get(&key).copied())
+    }
+}
+
+impl WriteOnlyHigherDb for SnapshotDb {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], _depth: u8) -> Option<Vec<u8>> {
+        let old = self.get_leaf(key);
+        self.pending.leaves.insert(key, value);
+        old.map(|old| old.to_vec())
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, _depth: u8) -> Option<StemMeta> {
+        let old = self.get_stem_meta(key);
+        self.pending.stems.insert(key, meta);
+        old
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        _depth: u8,
+    ) -> Option<BranchChild> {
+        let old = self.lookup(|tables| tables.branches.get(&branch_child_id).cloned());
+        self.pending
+            .branches
+            .insert(branch_child_id, BranchChild::Stem(stem_id));
+        old
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, _depth: u8) -> Option<BranchMeta> {
+        let old = self.get_branch_meta(&key);
+        self.pending.branches.insert(key, BranchChild::Branch(meta));
+        old
+    }
+}
+
+pub fn snapshots_share_structure() {
+    let mut trie = Trie::new(DefaultConfig::new(SnapshotDb::new()));
+
+    let mut keys = Vec::new();
+    for i in 0..=255u8 {
+        let mut key = [i; 32];
+        key[31] = 0;
+        keys.push(key);
+        trie.insert_single(key, key);
+    }
+    let old_root = trie.root_commitment();
+
+    let start = Instant::now();
+    let snapshot = trie.storage.snapshot();
+    println!("snapshot of {} leaves: {:?}", keys.len(), start.elapsed());
+
+    // Overwrite a leaf, add a leaf to an existing stem and add a new stem
+    trie.insert_single(keys[0], [0xff; 32]);
+    let mut key = keys[1];
+    key[31] = 1;
+    trie.insert_single(key, key);
+    let mut new_stem = keys[2];
+    new_stem[1] = 0;
+    trie.insert_single(new_stem, new_stem);
+    let new_root = trie.root_commitment();
+    assert_ne!(old_root, new_root);
+
+    // Only the touched nodes were copied
+    assert_eq!(trie.storage.pending.leaves.len(), 3);
+    assert!(trie.storage.pending.branches.len() < 10);
+
+    assert_eq!(snapshot.get_branch_meta(&[]).unwrap().commitment, old_root);
+    assert_eq!(snapshot.get_leaf(keys[0]), Some(keys[0]));
+    assert_eq!(snapshot.get_leaf(key), None);
+    assert_eq!(trie.get(keys[0]), Some([0xff; 32]));
+
+    let proof_keys = vec![keys[0], key, new_stem];
+    let proof = prover::create_verkle_proof(&snapshot, proof_keys.clone()).unwrap();
+    let (ok, _) = proof.check(
+        proof_keys.clone(),
+        vec![Some(keys[0]), None, None],
+        old_root,
+    );
+    assert!(ok);
+
+    let proof = prover::create_verkle_proof(&trie.storage, proof_keys.clone()).unwrap();
+    let (ok, _) = proof.check(
+        proof_keys,
+        vec![Some([0xff; 32]), Some(key), Some(new_stem)],
+        new_root,
+    );
+    assert!(ok);
+
+    // A snapshot can be forked and written to without affecting either side
+    let mut fork = Trie::new(Config {
+        db: snapshot,
+        committer: trie.committer.clone(),
+    });
+    fork.insert_single(keys[3], [0u8; 32]);
+    assert_eq!(trie.get(keys[3]), Some(keys[3]));
+    assert_eq!(trie.root_commitment(), new_root);
+    assert_eq!(trie.storage.layers(), 1);
+    drop(fork);
+
+    // Snapshots that are dropped do not leave layers behind
+    for (i, key) in keys.iter().enumerate().take(100) {
+        let before = trie.get(*key);
+        let snapshot = trie.storage.snapshot();
+        trie.insert_single(*key, [i as u8; 32]);
+        assert_eq!(snapshot.get_leaf(*key), before);
+    }
+    assert!(trie.storage.layers() <= 1);
+
+    // Live snapshots each keep a layer, and give them back once dropped
+    let live: Vec<_> = (0..10u8)
+        .map(|i| {
+            trie.insert_single(keys[200 + i as usize], [i; 32]);
+            trie.storage.snapshot()
+        })
+        .collect();
+    assert_eq!(trie.storage.layers(), 10);
+    assert_eq!(live[0].get_leaf(keys[201]), Some(keys[201]));
+    drop(live);
+    let root = trie.root_commitment();
+    let _snapshot = trie.storage.snapshot();
+    assert_eq!(trie.storage.layers(), 1);
+    assert_eq!(trie.root_commitment(), root);
+    assert_eq!(trie.get(keys[209]), Some([9; 32]));
+}
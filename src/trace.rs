@@ -0,0 +1,454 @@
+use banderwagon::{trait_defs::*, Element, Fr};
+use ipa_multipoint::committer::Committer;
+use std::fmt;
+use std::ops::Mul;
+use verkle_trie::{
+    constants::{CRS, TWO_POW_128},
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    group_to_field,
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::codec::{format_point, format_scalar};
+use crate::leaf::{split_value, LeafValue};
+
+// Every intermediate value of a single insert, from the leaf up to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    Leaf {
+        key: [u8; 32],
+        old_value: Option<[u8; 32]>,
+        new_value: [u8; 32],
+    },
+    // value_low carries the 2^128 marker, an absent leaf is (0, 0)
+    ValueScalars {
+        old_low: Fr,
+        old_high: Fr,
+        new_low: Fr,
+        new_high: Fr,
+    },
+    // C1 for suffixes below 128, C2 for the rest, at indices 2 * (suffix % 128) and the one after
+    LeafCommitment {
+        c2: bool,
+        indices: (usize, usize),
+        old: Element,
+        delta: Element,
+        new: Element,
+        old_hash: Fr,
+        new_hash: Fr,
+    },
+    StemCommitment {
+        stem: [u8; 31],
+        old: Option<Element>,
+        hash_c1: Fr,
+        hash_c2: Fr,
+        new: Element,
+        new_hash: Fr,
+    },
+    // (child index, old child hash, new child hash) for every child that changed
+    BranchCommitment {
+        path: Vec<u8>,
+        changes: Vec<(u8, Fr, Fr)>,
+        old: Element,
+        new: Element,
+        new_hash: Fr,
+    },
+    Root {
+        commitment: Element,
+        hash: Fr,
+    },
+    // The value computed from the spec differs from what the trie stored, or the
+    // trie is missing a node. Follows the event of the step it belongs to.
+    Mismatch {
+        step: String,
+        computed: String,
+        stored: String,
+    },
+}
+
+impl TraceEvent {
+    pub fn is_mismatch(&self) -> bool {
+        matches!(self, TraceEvent::Mismatch { .. })
+    }
+}
+
+fn check(events: &mut Vec<TraceEvent>, step: String, computed: String, stored: String) {
+    if computed != stored {
+        events.push(TraceEvent::Mismatch {
+            step,
+            computed,
+            stored,
+        });
+    }
+}
+
+fn value(value: &Option<[u8; 32]>) -> String {
+    value.map_or("absent".to_string(), hex::encode)
+}
+
+// One line of key=value pairs per event. Scalars are little endian, points compressed.
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceEvent::Leaf {
+                key,
+                old_value,
+                new_value,
+            } => write!(
+                f,
+                "step=leaf key={} old_value={} new_value={}",
+                hex::encode(key),
+                value(old_value),
+                hex::encode(new_value)
+            ),
+            TraceEvent::ValueScalars {
+                old_low,
+                old_high,
+                new_low,
+                new_high,
+            } => write!(
+                f,
+                "step=value_scalars old_low={} old_high={} new_low={} new_high={}",
+                format_scalar(old_low),
+                format_scalar(old_high),
+                format_scalar(new_low),
+                format_scalar(new_high)
+            ),
+            TraceEvent::LeafCommitment {
+                c2,
+                indices,
+                old,
+                delta,
+                new,
+                old_hash,
+                new_hash,
+            } => write!(
+                f,
+                "step={} indices={},{} old={} delta={} new={} old_hash={} new_hash={}",
+                if *c2 { "c2" } else { "c1" },
+                indices.0,
+                indices.1,
+                format_point(old),
+                format_point(delta),
+                format_point(new),
+                format_scalar(old_hash),
+                format_scalar(new_hash)
+            ),
+            TraceEvent::StemCommitment {
+                stem,
+                old,
+                hash_c1,
+                hash_c2,
+                new,
+                new_hash,
+            } => write!(
+                f,
+                "step=stem stem={} old={} hash_c1={} hash_c2={} new={} new_hash={}",
+                hex::encode(stem),
+                old.as_ref().map_or("none".to_string(), format_point),
+                format_scalar(hash_c1),
+                format_scalar(hash_c2),
+                format_point(new),
+                format_scalar(new_hash)
+            ),
+            TraceEvent::BranchCommitment {
+                path,
+                changes,
+                old,
+                new,
+                new_hash,
+            } => {
+                write!(f, "step=branch path={} changes=", hex::encode(path))?;
+                for (i, (index, old_hash, new_hash)) in changes.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(
+                        f,
+                        "{}:{}->{}",
+                        index,
+                        format_scalar(old_hash),
+                        format_scalar(new_hash)
+                    )?;
+                }
+                write!(
+                    f,
+                    " old={} new={} new_hash={}",
+                    format_point(old),
+                    format_point(new),
+                    format_scalar(new_hash)
+                )
+            }
+            TraceEvent::Mismatch {
+                step,
+                computed,
+                stored,
+            } => write!(
+                f,
+                "step=mismatch at={} computed={} stored={}",
+                step, computed, stored
+            ),
+            TraceEvent::Root { commitment, hash } => {
+                write!(
+                    f,
+                    "step=root commitment={} hash={}",
+                    format_point(commitment),
+                    format_scalar(hash)
+                )
+            }
+        }
+    }
+}
+
+fn child_hash<DB: ReadOnlyHigherDb>(storage: &DB, path: &[u8], index: u8) -> Fr {
+    match storage.get_branch_child(path, index) {
+        None => Fr::zero(),
+        Some(BranchChild::Stem(stem)) => storage.get_stem_meta(stem).unwrap().hash_stem_commitment,
+        Some(BranchChild::Branch(meta)) => meta.hash_commitment,
+    }
+}
+
+// Inserts the key/value and returns every intermediate value of the insert.
+// The values are computed independently from CRS points and checked against
+// what the trie stored. A difference is recorded as a `Mismatch` event and the
+// trace carries on, so a disagreement with the spec still gives a full trace.
+pub fn trace_insert<DB, C>(
+    trie: &mut Trie<DB, C>,
+    key: [u8; 32],
+    new_value: [u8; 32],
+) -> Vec<TraceEvent>
+where
+    DB: ReadOnlyHigherDb + WriteOnlyHigherDb,
+    C: Committer,
+{
+    let stem: [u8; 31] = key[0..31].try_into().unwrap();
+    let suffix = key[31];
+
+    // The state before the insert, for every branch the stem could end up under
+    let old_stem_meta = trie.storage.get_stem_meta(stem);
+    let old_value = trie.storage.get_leaf(key);
+    let old_branches: Vec<(Option<BranchMeta>, Fr)> = (0..31)
+        .map(|depth| {
+            let path = &stem[..depth];
+            (
+                trie.storage.get_branch_meta(path),
+                child_hash(&trie.storage, path, stem[depth]),
+            )
+        })
+        .collect();
+
+    trie.insert_single(key, new_value);
+
+    let mut events = vec![TraceEvent::Leaf {
+        key,
+        old_value,
+        new_value,
+    }];
+
+    let (old_low, old_high) = LeafValue::from(old_value).to_scalars();
+    let (new_low, new_high) = split_value(&new_value);
+    events.push(TraceEvent::ValueScalars {
+        old_low,
+        old_high,
+        new_low,
+        new_high,
+    });
+
+    // C_new = C_old + (new_low - old_low) * G_2i + (new_high - old_high) * G_2i+1
+    let c2 = suffix >= 128;
+    let offset = 2 * (suffix as usize % 128);
+    let old_c = match old_stem_meta {
+        Some(meta) if c2 => meta.c_2,
+        Some(meta) => meta.c_1,
+        None => Element::zero(),
+    };
+    let delta = CRS[offset].mul(new_low - old_low) + CRS[offset + 1].mul(new_high - old_high);
+    let new_c = old_c + delta;
+
+    let stem_meta = match trie.storage.get_stem_meta(stem) {
+        Some(meta) => meta,
+        None => {
+            events.push(TraceEvent::Mismatch {
+                step: format!("stem {}", hex::encode(stem)),
+                computed: format_point(&new_c),
+                stored: "missing".to_string(),
+            });
+            return events;
+        }
+    };
+    events.push(TraceEvent::LeafCommitment {
+        c2,
+        indices: (offset, offset + 1),
+        old: old_c,
+        delta,
+        new: new_c,
+        old_hash: group_to_field(&old_c),
+        new_hash: group_to_field(&new_c),
+    });
+    check(
+        &mut events,
+        if c2 { "c2" } else { "c1" }.to_string(),
+        format_point(&new_c),
+        format_point(if c2 { &stem_meta.c_2 } else { &stem_meta.c_1 }),
+    );
+
+    // 1 * G_0 + stem * G_1 + hash(C1) * G_2 + hash(C2) * G_3
+    let stem_commitment = CRS[0]
+        + CRS[1].mul(Fr::from_le_bytes_mod_order(&stem))
+        + CRS[2].mul(stem_meta.hash_c1)
+        + CRS[3].mul(stem_meta.hash_c2);
+    events.push(TraceEvent::StemCommitment {
+        stem,
+        old: old_stem_meta.map(|meta| meta.stem_commitment),
+        hash_c1: stem_meta.hash_c1,
+        hash_c2: stem_meta.hash_c2,
+        new: stem_commitment,
+        new_hash: group_to_field(&stem_commitment),
+    });
+    check(
+        &mut events,
+        format!("stem {}", hex::encode(stem)),
+        format_point(&stem_commitment),
+        format_point(&stem_meta.stem_commitment),
+    );
+
+    // Branches on the path after the insert, from the stem's parent up to the root
+    let mut depth = 0;
+    while depth < 31 && trie.storage.get_branch_meta(&stem[..=depth]).is_some() {
+        depth += 1;
+    }
+    for depth in (0..=depth).rev() {
+        let path = &stem[..depth];
+        let new_meta = trie.storage.get_branch_meta(path).unwrap();
+
+        let (old, changes) = match old_branches[depth].0 {
+            Some(old_meta) => (
+                old_meta.commitment,
+                vec![(
+                    stem[depth],
+                    old_branches[depth].1,
+                    child_hash(&trie.storage, path, stem[depth]),
+                )],
+            ),
+            // A branch created by this insert, all of its children are new
+            None => (
+                Element::zero(),
+                trie.storage
+                    .get_branch_children(path)
+                    .into_iter()
+                    .map(|(index, _)| (index, Fr::zero(), child_hash(&trie.storage, path, index)))
+                    .collect(),
+            ),
+        };
+
+        let expected = changes
+            .iter()
+            .fold(old, |acc, (index, old_hash, new_hash)| {
+                acc + CRS[*index as usize].mul(*new_hash - *old_hash)
+            });
+        events.push(TraceEvent::BranchCommitment {
+            path: path.to_vec(),
+            changes,
+            old,
+            new: new_meta.commitment,
+            new_hash: new_meta.hash_commitment,
+        });
+        check(
+            &mut events,
+            format!("branch {}", hex::encode(path)),
+            format_point(&expected),
+            format_point(&new_meta.commitment),
+        );
+    }
+
+    let root = trie.root_commitment();
+    events.push(TraceEvent::Root {
+        commitment: root,
+        hash: trie.root_hash(),
+    });
+    check(
+        &mut events,
+        "root hash".to_string(),
+        format_scalar(&group_to_field(&root)),
+        format_scalar(&trie.root_hash()),
+    );
+    events
+}
+
+pub fn print_trace(events: &[TraceEvent]) {
+    for event in events {
+        println!("{}", event);
+    }
+}
+
+// `trie::insert_key1_val1` followed by an update, a second stem and a long shared prefix
+pub fn trace_insert_scenarios() {
+    let mut trie = Trie::new(DefaultConfig::new(MemoryDb::new()));
+
+    let key = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    let events = trace_insert(&mut trie, key, key);
+    print_trace(&events);
+    assert!(!events.iter().any(TraceEvent::is_mismatch));
+
+    // The values insert_key1_val1 asserts by hand
+    let value_low =
+        Fr::from_le_bytes_mod_order(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+            + TWO_POW_128;
+    let value_high = Fr::from_le_bytes_mod_order(&[
+        17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32,
+    ]);
+    assert_eq!(
+        events[1],
+        TraceEvent::ValueScalars {
+            old_low: Fr::zero(),
+            old_high: Fr::zero(),
+            new_low: value_low,
+            new_high: value_high,
+        }
+    );
+    match &events[2] {
+        TraceEvent::LeafCommitment {
+            c2, indices, new, ..
+        } => {
+            assert!(!c2);
+            assert_eq!(*indices, (64, 65));
+            assert_eq!(*new, CRS[64].mul(value_low) + CRS[65].mul(value_high));
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    let mut key_c2 = key;
+    key_c2[31] = 200;
+    let mut key_deep = key;
+    key_deep[30] = 0;
+    for (key, value) in [(key, [0u8; 32]), (key_c2, key_c2), (key_deep, key_deep)] {
+        println!();
+        let events = trace_insert(&mut trie, key, value);
+        print_trace(&events);
+        assert!(!events.iter().any(TraceEvent::is_mismatch));
+    }
+
+    // A stored stem commitment that does not match its C1/C2 is carried along by
+    // the delta update, and shows up as a mismatch instead of stopping the trace
+    let stem: [u8; 31] = key[0..31].try_into().unwrap();
+    let mut meta = trie.storage.get_stem_meta(stem).unwrap();
+    meta.stem_commitment = meta.stem_commitment + CRS[4];
+    // key and key_deep part at byte 30, so the stem hangs off a branch at depth 30
+    trie.storage.insert_stem(stem, meta, 30);
+    println!();
+    let events = trace_insert(&mut trie, key, key);
+    print_trace(&events);
+    assert!(matches!(
+        events.iter().find(|event| event.is_mismatch()),
+        Some(TraceEvent::Mismatch { step, .. }) if step.starts_with("stem")
+    ));
+    assert!(matches!(events.last(), Some(TraceEvent::Root { .. })));
+}
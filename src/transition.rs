@@ -0,0 +1,249 @@
+use banderwagon::Fr;
+use ipa_multipoint::committer::DefaultCommitter;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use verkle_trie::{
+    database::memory_db::MemoryDb, proof::prover, trie::Trie, DefaultConfig, TrieTrait,
+};
+
+use crate::batch::batch_insert;
+use crate::workload::{scenario_seed, Distribution, Workload};
+
+// Rough size of a hexary MPT node with 16 hashes and some RLP overhead,
+// and of the account/storage leaf at the end of a proof
+const MPT_BRANCH_NODE_BYTES: usize = 532;
+const MPT_LEAF_NODE_BYTES: usize = 110;
+
+// A local stand-in for the MPT: keys are already verkle tree keys, and a proof
+// for a key is modelled as one branch node per level of a balanced hexary trie
+#[derive(Debug, Clone, Default)]
+pub struct LegacyState {
+    leaves: BTreeMap<[u8; 32], [u8; 32]>,
+}
+
+impl LegacyState {
+    pub fn new(leaves: BTreeMap<[u8; 32], [u8; 32]>) -> LegacyState {
+        LegacyState { leaves }
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<[u8; 32]> {
+        self.leaves.get(key).copied()
+    }
+
+    pub fn proof_size(&self, keys: usize) -> usize {
+        let mut depth = 1;
+        while 16usize.pow(depth) < self.leaves.len() {
+            depth += 1;
+        }
+        keys * (depth as usize * MPT_BRANCH_NODE_BYTES + MPT_LEAF_NODE_BYTES)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    Verkle,
+    Legacy,
+    Absent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockReport {
+    pub block: u64,
+    pub migrated: usize,
+    pub total_migrated: usize,
+    pub remaining: usize,
+    pub root: Fr,
+    pub verkle_reads: usize,
+    pub legacy_reads: usize,
+    pub absent_reads: usize,
+    // Proof for every read key against the verkle root: presence for verkle hits,
+    // absence for everything that had to fall through to the legacy state
+    pub verkle_witness_bytes: usize,
+    pub legacy_witness_bytes: usize,
+}
+
+// EIP-7748 style conversion: reads go to the verkle trie first and fall back to the
+// legacy state, writes only go to the verkle trie, and every block moves the next
+// `leaves_per_block` legacy leaves (in key order) over to the verkle trie.
+// The legacy state is frozen, it is never written to.
+pub struct Overlay {
+    legacy: LegacyState,
+    verkle: Trie<MemoryDb, DefaultCommitter>,
+    // The last legacy key that was migrated
+    cursor: Option<[u8; 32]>,
+    leaves_per_block: usize,
+    total_migrated: usize,
+    block: u64,
+}
+
+impl Overlay {
+    pub fn new(legacy: LegacyState, leaves_per_block: usize) -> Overlay {
+        Overlay {
+            legacy,
+            verkle: Trie::new(DefaultConfig::new(MemoryDb::new())),
+            cursor: None,
+            leaves_per_block,
+            total_migrated: 0,
+            block: 0,
+        }
+    }
+
+    pub fn verkle(&self) -> &Trie<MemoryDb, DefaultCommitter> {
+        &self.verkle
+    }
+
+    pub fn is_converted(&self) -> bool {
+        self.total_migrated == self.legacy.len()
+    }
+
+    fn is_migrated(&self, key: &[u8; 32]) -> bool {
+        self.cursor.is_some_and(|cursor| *key <= cursor)
+    }
+
+    pub fn read(&self, key: [u8; 32]) -> (Option<[u8; 32]>, ReadSource) {
+        if let Some(value) = self.verkle.get(key) {
+            return (Some(value), ReadSource::Verkle);
+        }
+        // Anything at or before the cursor has been migrated, or overwritten since
+        if !self.is_migrated(&key) {
+            if let Some(value) = self.legacy.get(&key) {
+                return (Some(value), ReadSource::Legacy);
+            }
+        }
+        (None, ReadSource::Absent)
+    }
+
+    fn migrate(&mut self) -> usize {
+        let start = match self.cursor {
+            Some(cursor) => Bound::Excluded(cursor),
+            None => Bound::Unbounded,
+        };
+        let batch: Vec<_> = self
+            .legacy
+            .leaves
+            .range((start, Bound::Unbounded))
+            .take(self.leaves_per_block)
+            .map(|(key, value)| (*key, *value))
+            .collect();
+
+        if let Some((last, _)) = batch.last() {
+            self.cursor = Some(*last);
+        }
+        let migrated = batch.len();
+        self.total_migrated += migrated;
+
+        // A key written during the transition already holds a newer value in the verkle trie
+        let batch: Vec<_> = batch
+            .into_iter()
+            .filter(|(key, _)| self.verkle.get(*key).is_none())
+            .collect();
+        batch_insert(&mut self.verkle, batch);
+        migrated
+    }
+
+    // Executes a block: the reads and writes of its transactions, then the conversion step
+    pub fn apply_block(
+        &mut self,
+        reads: &[[u8; 32]],
+        writes: &[([u8; 32], [u8; 32])],
+    ) -> BlockReport {
+        self.block += 1;
+
+        let mut report = BlockReport {
+            block: self.block,
+            migrated: 0,
+            total_migrated: 0,
+            remaining: 0,
+            root: Fr::from(0u32),
+            verkle_reads: 0,
+            legacy_reads: 0,
+            absent_reads: 0,
+            verkle_witness_bytes: 0,
+            legacy_witness_bytes: 0,
+        };
+
+        for key in reads {
+            match self.read(*key).1 {
+                ReadSource::Verkle => report.verkle_reads += 1,
+                ReadSource::Legacy => report.legacy_reads += 1,
+                ReadSource::Absent => report.absent_reads += 1,
+            }
+        }
+        if !reads.is_empty() {
+            let proof = prover::create_verkle_proof(&self.verkle.storage, reads.to_vec()).unwrap();
+            let mut bytes = Vec::new();
+            proof.write(&mut bytes).unwrap();
+            report.verkle_witness_bytes = bytes.len();
+        }
+        report.legacy_witness_bytes = self.legacy.proof_size(report.legacy_reads);
+
+        batch_insert(&mut self.verkle, writes.iter().copied());
+
+        report.migrated = self.migrate();
+        report.total_migrated = self.total_migrated;
+        report.remaining = self.legacy.len() - self.total_migrated;
+        report.root = self.verkle.root_hash();
+        report
+    }
+}
+
+pub fn simulate_transition() {
+    let seed = scenario_seed("simulate_transition");
+    let mut rng = StdRng::seed_from_u64(seed);
+    let legacy: BTreeMap<[u8; 32], [u8; 32]> = Workload::new(seed, Distribution::Uniform)
+        .take(5_000)
+        .collect();
+    let legacy_keys: Vec<[u8; 32]> = legacy.keys().copied().collect();
+
+    let mut expected = legacy.clone();
+    let mut overlay = Overlay::new(LegacyState::new(legacy), 500);
+
+    println!("block\tmigrated\tremaining\tverkle/legacy/absent reads\tverkle witness\tlegacy witness\troot");
+    while !overlay.is_converted() {
+        let reads: Vec<[u8; 32]> = (0..32)
+            .map(|i| match i % 4 {
+                3 => rng.gen(),
+                _ => legacy_keys[rng.gen_range(0..legacy_keys.len())],
+            })
+            .collect();
+        let writes: Vec<([u8; 32], [u8; 32])> = (0..16)
+            .map(|i| match i % 2 {
+                0 => (legacy_keys[rng.gen_range(0..legacy_keys.len())], rng.gen()),
+                _ => (rng.gen(), rng.gen()),
+            })
+            .collect();
+
+        for key in &reads {
+            assert_eq!(overlay.read(*key).0, expected.get(key).copied());
+        }
+        expected.extend(writes.iter().copied());
+
+        let report = overlay.apply_block(&reads, &writes);
+        println!(
+            "{}\t{}\t{}\t{}/{}/{}\t{}\t{}\t{:?}",
+            report.block,
+            report.migrated,
+            report.remaining,
+            report.verkle_reads,
+            report.legacy_reads,
+            report.absent_reads,
+            report.verkle_witness_bytes,
+            report.legacy_witness_bytes,
+            report.root
+        );
+    }
+
+    // Once converted, the verkle trie holds the whole state
+    let mut converted = Trie::new(DefaultConfig::new(MemoryDb::new()));
+    batch_insert(&mut converted, expected.iter().map(|(k, v)| (*k, *v)));
+    assert_eq!(converted.root_hash(), overlay.verkle().root_hash());
+}
@@ -0,0 +1,436 @@
+use banderwagon::Element;
+use ipa_multipoint::committer::DefaultCommitter;
+use std::collections::HashMap;
+use verkle_trie::{
+    config::Config,
+    database::{
+        memory_db::MemoryDb,
+        meta::{BranchChild, BranchMeta, StemMeta},
+        ReadOnlyHigherDb, WriteOnlyHigherDb,
+    },
+    proof::{prover, VerkleProof},
+    trie::Trie,
+    DefaultConfig, TrieTrait,
+};
+
+use crate::batch::batch_insert;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeafChange {
+    pub key: [u8; 32],
+    pub old_value: Option<[u8; 32]>,
+    pub new_value: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRecord {
+    pub number: u64,
+    pub root: Element,
+    pub changes: Vec<LeafChange>,
+}
+
+// What a block overwrote in the node tables, with the value from before the block.
+// `None` is a node the block created.
+#[derive(Debug, Default)]
+struct NodeDiff {
+    leaves: HashMap<[u8; 32], Option<[u8; 32]>>,
+    stems: HashMap<[u8; 31], Option<StemMeta>>,
+    branches: HashMap<Vec<u8>, Option<BranchChild>>,
+}
+
+// The latest state, recording the old value of every node the first time
+// the current block writes to it
+#[derive(Debug, Default)]
+pub struct HistoryDb {
+    latest: MemoryDb,
+    pending: NodeDiff,
+}
+
+impl HistoryDb {
+    pub fn new() -> HistoryDb {
+        HistoryDb::default()
+    }
+}
+
+impl ReadOnlyHigherDb for HistoryDb {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.latest.get_stem_meta(stem_key)
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        self.latest.get_branch_children(branch_id)
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        self.latest.get_branch_meta(key)
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        self.latest.get_branch_child(branch_id, index)
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        self.latest.get_stem_children(stem_key)
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.latest.get_leaf(key)
+    }
+}
+
+impl WriteOnlyHigherDb for HistoryDb {
+    fn insert_leaf(&mut self, key: [u8; 32], value: [u8; 32], depth: u8) -> Option<Vec<u8>> {
+        let old = self.latest.get_leaf(key);
+        self.pending.leaves.entry(key).or_insert(old);
+        self.latest.insert_leaf(key, value, depth)
+    }
+
+    fn insert_stem(&mut self, key: [u8; 31], meta: StemMeta, depth: u8) -> Option<StemMeta> {
+        let old = self.latest.get_stem_meta(key);
+        self.pending.stems.entry(key).or_insert(old);
+        self.latest.insert_stem(key, meta, depth)
+    }
+
+    fn add_stem_as_branch_child(
+        &mut self,
+        branch_child_id: Vec<u8>,
+        stem_id: [u8; 31],
+        depth: u8,
+    ) -> Option<BranchChild> {
+        let old = branch_node(&self.latest, &branch_child_id);
+        self.pending
+            .branches
+            .entry(branch_child_id.clone())
+            .or_insert(old);
+        self.latest
+            .add_stem_as_branch_child(branch_child_id, stem_id, depth)
+    }
+
+    fn insert_branch(&mut self, key: Vec<u8>, meta: BranchMeta, depth: u8) -> Option<BranchMeta> {
+        let old = branch_node(&self.latest, &key);
+        self.pending.branches.entry(key.clone()).or_insert(old);
+        self.latest.insert_branch(key, meta, depth)
+    }
+}
+
+// The branch table entry at `path`, which is a branch or a stem
+fn branch_node<DB: ReadOnlyHigherDb>(storage: &DB, path: &[u8]) -> Option<BranchChild> {
+    match path.split_last() {
+        Some((index, parent)) => storage.get_branch_child(parent, *index),
+        None => storage.get_branch_meta(path).map(BranchChild::Branch),
+    }
+}
+
+// The state at the end of an earlier block: the latest state seen through the diffs of
+// every block after it, oldest first, so the first diff that touched a node has its value
+pub struct HistoricalView<'a> {
+    latest: &'a MemoryDb,
+    later_blocks: &'a [NodeDiff],
+}
+
+impl HistoricalView<'_> {
+    fn lookup<T>(
+        &self,
+        diff_entry: impl Fn(&NodeDiff) -> Option<Option<T>>,
+        latest: impl FnOnce(&MemoryDb) -> Option<T>,
+    ) -> Option<T> {
+        self.later_blocks
+            .iter()
+            .find_map(diff_entry)
+            .unwrap_or_else(|| latest(self.latest))
+    }
+}
+
+impl ReadOnlyHigherDb for HistoricalView<'_> {
+    fn get_stem_meta(&self, stem_key: [u8; 31]) -> Option<StemMeta> {
+        self.lookup(
+            |diff| diff.stems.get(&stem_key).copied(),
+            |latest| latest.get_stem_meta(stem_key),
+        )
+    }
+
+    fn get_branch_children(&self, branch_id: &[u8]) -> Vec<(u8, BranchChild)> {
+        (0..=255u8)
+            .filter_map(|index| {
+                self.get_branch_child(branch_id, index)
+                    .map(|child| (index, child))
+            })
+            .collect()
+    }
+
+    fn get_branch_meta(&self, key: &[u8]) -> Option<BranchMeta> {
+        let node = self.lookup(
+            |diff| diff.branches.get(key).cloned(),
+            |latest| branch_node(latest, key),
+        );
+        match node {
+            Some(BranchChild::Branch(meta)) => Some(meta),
+            _ => None,
+        }
+    }
+
+    fn get_branch_child(&self, branch_id: &[u8], index: u8) -> Option<BranchChild> {
+        let mut path = branch_id.to_vec();
+        path.push(index);
+        self.lookup(
+            |diff| diff.branches.get(&path).cloned(),
+            |latest| latest.get_branch_child(branch_id, index),
+        )
+    }
+
+    fn get_stem_children(&self, stem_key: [u8; 31]) -> Vec<(u8, [u8; 32])> {
+        let mut key = [0u8; 32];
+        key[..31].copy_from_slice(&stem_key);
+        (0..=255u8)
+            .filter_map(|suffix| {
+                key[31] = suffix;
+                self.get_leaf(key).map(|value| (suffix, value))
+            })
+            .collect()
+    }
+
+    fn get_leaf(&self, key: [u8; 32]) -> Option<[u8; 32]> {
+        self.lookup(
+            |diff| diff.leaves.get(&key).copied(),
+            |latest| latest.get_leaf(key),
+        )
+    }
+}
+
+// Keeps only the latest trie, plus for every committed block its root, the leaves it
+// changed and what it overwrote in the node tables. Proofs against an old root go through
+// a view of the latest nodes with the later blocks' writes undone, nothing is rebuilt.
+//
+// Block 0 is the empty trie.
+pub struct VersionedTrie {
+    trie: Trie<HistoryDb, DefaultCommitter>,
+    blocks: Vec<BlockRecord>,
+    // undo[n - 1] is what block n overwrote
+    undo: Vec<NodeDiff>,
+    // Every value a key has had, with the block that wrote it, in block order
+    history: HashMap<[u8; 32], Vec<(u64, [u8; 32])>>,
+}
+
+impl Default for VersionedTrie {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VersionedTrie {
+    pub fn new() -> VersionedTrie {
+        let mut trie = Trie::new(DefaultConfig::new(HistoryDb::new()));
+        // The empty root is part of block 0
+        trie.storage.pending = NodeDiff::default();
+        let genesis = BlockRecord {
+            number: 0,
+            root: trie.root_commitment(),
+            changes: vec![],
+        };
+        VersionedTrie {
+            trie,
+            blocks: vec![genesis],
+            undo: Vec::new(),
+            history: HashMap::new(),
+        }
+    }
+
+    pub fn latest_block(&self) -> u64 {
+        self.blocks.len() as u64 - 1
+    }
+
+    pub fn latest(&self) -> &Trie<HistoryDb, DefaultCommitter> {
+        &self.trie
+    }
+
+    pub fn block(&self, number: u64) -> Option<&BlockRecord> {
+        self.blocks.get(number as usize)
+    }
+
+    pub fn root_at(&self, number: u64) -> Option<Element> {
+        self.block(number).map(|block| block.root)
+    }
+
+    // Applies the writes as the next block and returns its number
+    pub fn commit_block(&mut self, writes: impl IntoIterator<Item = ([u8; 32], [u8; 32])>) -> u64 {
+        let number = self.latest_block() + 1;
+
+        // Only the last write to a key within a block is kept
+        let mut last: HashMap<[u8; 32], [u8; 32]> = HashMap::new();
+        let mut order = Vec::new();
+        for (key, value) in writes {
+            if last.insert(key, value).is_none() {
+                order.push(key);
+            }
+        }
+
+        let mut changes = Vec::with_capacity(order.len());
+        for key in order {
+            let new_value = last[&key];
+            let old_value = self.trie.get(key);
+            if old_value == Some(new_value) {
+                continue;
+            }
+            self.history
+                .entry(key)
+                .or_default()
+                .push((number, new_value));
+            changes.push(LeafChange {
+                key,
+                old_value,
+                new_value,
+            });
+        }
+
+        batch_insert(
+            &mut self.trie,
+            changes.iter().map(|change| (change.key, change.new_value)),
+        );
+        self.undo
+            .push(std::mem::take(&mut self.trie.storage.pending));
+        self.blocks.push(BlockRecord {
+            number,
+            root: self.trie.root_commitment(),
+            changes,
+        });
+        number
+    }
+
+    // The value of `key` as of the end of block `at_block`, `None` for a block
+    // that has not been committed yet
+    pub fn get(&self, key: [u8; 32], at_block: u64) -> Option<[u8; 32]> {
+        if at_block > self.latest_block() {
+            return None;
+        }
+        let versions = self.history.get(&key)?;
+        let count = versions.partition_point(|(number, _)| *number <= at_block);
+        count.checked_sub(1).map(|i| versions[i].1)
+    }
+
+    // The nodes as they were at the end of `at_block`
+    pub fn view_at(&self, at_block: u64) -> Option<HistoricalView<'_>> {
+        if at_block > self.latest_block() {
+            return None;
+        }
+        Some(HistoricalView {
+            latest: &self.trie.storage.latest,
+            later_blocks: &self.undo[at_block as usize..],
+        })
+    }
+
+    // Rebuilds the trie as it was at the end of `at_block` from the per-key history.
+    // This recommits the whole state, it is only meant to cross-check `view_at`.
+    pub fn trie_at(&self, at_block: u64) -> Option<Trie<MemoryDb, DefaultCommitter>> {
+        if at_block > self.latest_block() {
+            return None;
+        }
+
+        let mut trie = Trie::new(Config {
+            db: MemoryDb::new(),
+            committer: self.trie.committer.clone(),
+        });
+        let leaves = self
+            .history
+            .keys()
+            .filter_map(|key| self.get(*key, at_block).map(|value| (*key, value)));
+        batch_insert(&mut trie, leaves);
+
+        debug_assert_eq!(Some(trie.root_commitment()), self.root_at(at_block));
+        Some(trie)
+    }
+
+    // A proof for `keys` against the root of `at_block`, along with that root
+    pub fn prove_at(&self, keys: Vec<[u8; 32]>, at_block: u64) -> Option<(VerkleProof, Element)> {
+        let root = self.root_at(at_block)?;
+        let view = self.view_at(at_block)?;
+        debug_assert_eq!(
+            view.get_branch_meta(&[]).map(|meta| meta.commitment),
+            Some(root)
+        );
+        debug_assert_eq!(
+            self.trie_at(at_block).map(|trie| trie.root_commitment()),
+            Some(root)
+        );
+        let proof = prover::create_verkle_proof(&view, keys).unwrap();
+        Some((proof, root))
+    }
+}
+
+// The scenario of `trie::simple_insert`, without cloning the trie to keep the old version
+pub fn historical_reads_and_proofs() {
+    let key1 = [
+        0, 147, 89, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31, 32,
+    ];
+    let key2 = [
+        1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+        26, 27, 28, 29, 30, 31, 32,
+    ];
+    let mut key3 = key2;
+    key3[31] = 0xff;
+    let mut key4 = key2;
+    key4[30] = 0xff;
+    let mut key5 = key2;
+    key5[..3].copy_from_slice(&[0xff, 0xff, 0xff]);
+    let mut key6 = key2;
+    key6[31] = 0x7f;
+
+    let mut versioned = VersionedTrie::new();
+    let block_1 = versioned.commit_block([key1, key2, key3, key4, key5].map(|key| (key, key)));
+    let block_2 = versioned.commit_block([(key6, key6), (key2, [0u8; 32])]);
+    let block_3 = versioned.commit_block([(key2, key2)]);
+
+    assert_eq!(versioned.get(key6, block_1), None);
+    assert_eq!(versioned.get(key6, block_2), Some(key6));
+    assert_eq!(versioned.get(key2, 0), None);
+    assert_eq!(versioned.get(key2, block_1), Some(key2));
+    assert_eq!(versioned.get(key2, block_2), Some([0u8; 32]));
+    assert_eq!(versioned.get(key2, block_3), Some(key2));
+    assert_eq!(versioned.get(key2, block_3 + 1), None);
+    assert!(versioned.view_at(block_3 + 1).is_none());
+
+    let old = versioned.trie_at(block_1).unwrap();
+    assert_eq!(Some(old.root_commitment()), versioned.root_at(block_1));
+    assert_ne!(versioned.root_at(block_1), versioned.root_at(block_2));
+    assert_eq!(old.get(key6), None);
+
+    // The view of an old block sees the nodes the rebuilt trie has
+    let view = versioned.view_at(block_1).unwrap();
+    assert_eq!(view.get_leaf(key6), None);
+    assert_eq!(view.get_leaf(key2), Some(key2));
+    assert_eq!(
+        view.get_branch_meta(&[]).map(|meta| meta.commitment),
+        versioned.root_at(block_1)
+    );
+    let stem: [u8; 31] = key2[..31].try_into().unwrap();
+    assert_eq!(
+        view.get_stem_meta(stem).map(|meta| meta.stem_commitment),
+        old.storage
+            .get_stem_meta(stem)
+            .map(|meta| meta.stem_commitment)
+    );
+    assert_eq!(
+        versioned
+            .view_at(0)
+            .unwrap()
+            .get_branch_meta(&[])
+            .map(|meta| meta.commitment),
+        versioned.root_at(0)
+    );
+
+    // key6 is absent at block 1 and present at block 2, key2 is zero at block 2
+    for (block, values) in [
+        (block_1, vec![Some(key2), None]),
+        (block_2, vec![Some([0u8; 32]), Some(key6)]),
+        (block_3, vec![Some(key2), Some(key6)]),
+    ] {
+        let keys = vec![key2, key6];
+        let (proof, root) = versioned.prove_at(keys.clone(), block).unwrap();
+        let (ok, _) = proof.check(keys, values, root);
+        assert!(ok, "proof at block {}", block);
+    }
+
+    let changes = &versioned.block(block_2).unwrap().changes;
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[1].old_value, Some(key2));
+}
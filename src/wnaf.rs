@@ -0,0 +1,113 @@
+use ark_ff::biginteger::BigInteger;
+use banderwagon::{Element, Fr, PrimeField};
+use ipa_multipoint::committer::{Committer, DefaultCommitter};
+use rand::Rng;
+use std::time::{Duration, Instant};
+use verkle_trie::constants::CRS;
+
+use crate::workload::scenario_rng;
+
+// Precomputed odd multiples P, 3P, 5P, ..., (2^(w-1) - 1)P for every base.
+// A width-w NAF digit d is odd and |d| < 2^(w-1), so it indexes the table at (|d| - 1) / 2
+pub struct WnafTable {
+    window: usize,
+    tables: Vec<Vec<Element>>,
+}
+
+impl WnafTable {
+    pub fn new(bases: &[Element], window: usize) -> WnafTable {
+        assert!(
+            (2..64).contains(&window),
+            "find_wnaf only supports 2 <= w < 64"
+        );
+
+        let table_size = 1 << (window - 2);
+        let tables = bases
+            .iter()
+            .map(|base| {
+                let double = *base + *base;
+                let mut multiples = Vec::with_capacity(table_size);
+                multiples.push(*base);
+                for i in 1..table_size {
+                    multiples.push(multiples[i - 1] + double);
+                }
+                multiples
+            })
+            .collect();
+
+        WnafTable { window, tables }
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Interleaved wNAF: all scalars share the same chain of doublings
+    pub fn msm(&self, scalars: &[Fr]) -> Element {
+        assert!(scalars.len() <= self.tables.len());
+
+        let digits: Vec<Vec<i64>> = scalars
+            .iter()
+            .map(|scalar| scalar.into_bigint().find_wnaf(self.window).unwrap())
+            .collect();
+        let max_len = digits.iter().map(Vec::len).max().unwrap_or(0);
+
+        let mut acc = Element::zero();
+        for i in (0..max_len).rev() {
+            acc = acc + acc;
+            for (table, digits) in self.tables.iter().zip(&digits) {
+                match digits.get(i) {
+                    Some(&d) if d > 0 => acc = acc + table[(d as usize - 1) / 2],
+                    Some(&d) if d < 0 => acc = acc - table[((-d) as usize - 1) / 2],
+                    _ => {}
+                }
+            }
+        }
+        acc
+    }
+}
+
+fn average<T>(iterations: u32, mut f: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        std::hint::black_box(f());
+    }
+    start.elapsed() / iterations
+}
+
+pub fn wnaf_experiments() {
+    let mut rng = scenario_rng("wnaf_experiments");
+    let bases = &CRS.G;
+    let scalars: Vec<Fr> = (0..bases.len())
+        .map(|_| Fr::from_le_bytes_mod_order(&rng.gen::<[u8; 32]>()))
+        .collect();
+
+    let committer = DefaultCommitter::new(bases);
+    let expected = committer.commit_lagrange(&scalars);
+    let committer_time = average(10, || committer.commit_lagrange(&scalars));
+    println!("library committer: {:?}", committer_time);
+
+    println!("window\tprecompute\ttable points\tmsm");
+    for window in 2..=12 {
+        let start = Instant::now();
+        let table = WnafTable::new(bases, window);
+        let precompute = start.elapsed();
+
+        assert_eq!(table.msm(&scalars), expected, "window {}", window);
+
+        // Only a handful of non-zero scalars, like a single leaf update
+        let mut sparse = vec![Fr::from(0u32); bases.len()];
+        sparse[64] = scalars[64];
+        sparse[65] = scalars[65];
+        assert_eq!(table.msm(&sparse), committer.commit_lagrange(&sparse));
+
+        let msm = average(10, || table.msm(&scalars));
+        println!(
+            "{}\t{:?}\t{}\t{:?}",
+            table.window(),
+            precompute,
+            bases.len() << (window - 2),
+            msm
+        );
+    }
+}
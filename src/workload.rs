@@ -0,0 +1,256 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha3::{Digest, Keccak256};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::bulk::{read_csv, write_csv, KeyValue};
+use crate::genesis::{
+    storage_slot_position, BASIC_DATA_LEAF_KEY, CODE_HASH_LEAF_KEY, CODE_OFFSET,
+    HEADER_STORAGE_OFFSET,
+};
+
+// Set to replay a randomized scenario with the seed it printed
+pub const SEED_ENV: &str = "VERKLE_SEED";
+
+// The seed for a randomized scenario: `VERKLE_SEED` if set, a fresh one otherwise.
+// It is printed either way, so any failure can be replayed.
+pub fn scenario_seed(name: &str) -> u64 {
+    let seed = match std::env::var(SEED_ENV) {
+        Ok(seed) => seed.parse().expect("VERKLE_SEED has to be a u64"),
+        Err(_) => rand::random(),
+    };
+    println!(
+        "{}: seed {} (replay with {}={})",
+        name, seed, SEED_ENV, seed
+    );
+    seed
+}
+
+pub fn scenario_rng(name: &str) -> StdRng {
+    StdRng::seed_from_u64(scenario_seed(name))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    // Every key is 32 random bytes, so practically every key has its own stem
+    Uniform,
+    // Keys fall into `stems` random stems with random suffixes
+    ClusteredStems { stems: usize },
+    // Groups of keys per account, with stems derived from a hashed address the
+    // way EIP-6800 lays out the header and storage of an account
+    AccountLike { slots: u64 },
+    // Every key starts with the same `prefix_len` random bytes, which forces
+    // a chain of branches that deep before keys are told apart
+    SharedPrefix { prefix_len: usize },
+    // Random keys, half of which share a prefix of random length with an earlier
+    // key, so deep branches and stems with several leaves show up in one stream
+    MixedPrefixes,
+}
+
+// A reproducible stream of key/values. The same seed and distribution always
+// give the same stream, so a failing scenario can be replayed from its seed.
+pub struct Workload {
+    seed: u64,
+    distribution: Distribution,
+    rng: StdRng,
+    stems: Vec<[u8; 31]>,
+    prefix: Vec<u8>,
+    // Keys of the current account still to be emitted, for `AccountLike`
+    pending: Vec<[u8; 32]>,
+    // Keys emitted so far, for `MixedPrefixes`
+    history: Vec<[u8; 32]>,
+}
+
+impl Workload {
+    pub fn new(seed: u64, distribution: Distribution) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let stems = match distribution {
+            Distribution::ClusteredStems { stems } => {
+                assert!(stems > 0, "a clustered workload needs at least one stem");
+                (0..stems).map(|_| rng.gen()).collect()
+            }
+            _ => Vec::new(),
+        };
+        let prefix = match distribution {
+            Distribution::SharedPrefix { prefix_len } => {
+                assert!(
+                    prefix_len <= 31,
+                    "the prefix has to leave the suffix byte free"
+                );
+                (0..prefix_len).map(|_| rng.gen()).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        Workload {
+            seed,
+            distribution,
+            rng,
+            stems,
+            prefix,
+            pending: Vec::new(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn distribution(&self) -> Distribution {
+        self.distribution
+    }
+
+    pub fn next_key(&mut self) -> [u8; 32] {
+        match self.distribution {
+            Distribution::Uniform => self.rng.gen(),
+            Distribution::ClusteredStems { .. } => {
+                let mut key = [0u8; 32];
+                key[0..31].copy_from_slice(&self.stems[self.rng.gen_range(0..self.stems.len())]);
+                key[31] = self.rng.gen();
+                key
+            }
+            Distribution::AccountLike { slots } => {
+                if self.pending.is_empty() {
+                    let address: [u8; 20] = self.rng.gen();
+                    self.pending = account_keys(&address, slots);
+                    self.pending.reverse();
+                }
+                self.pending.pop().unwrap()
+            }
+            Distribution::SharedPrefix { .. } => {
+                let mut key: [u8; 32] = self.rng.gen();
+                key[0..self.prefix.len()].copy_from_slice(&self.prefix);
+                key
+            }
+            Distribution::MixedPrefixes => {
+                let mut key: [u8; 32] = self.rng.gen();
+                if !self.history.is_empty() && self.rng.gen_bool(0.5) {
+                    let other = self.history[self.rng.gen_range(0..self.history.len())];
+                    let shared = self.rng.gen_range(1..=32);
+                    key[..shared].copy_from_slice(&other[..shared]);
+                }
+                self.history.push(key);
+                key
+            }
+        }
+    }
+
+    pub fn next_value(&mut self) -> [u8; 32] {
+        self.rng.gen()
+    }
+
+    pub fn keys(&mut self, n: usize) -> Vec<[u8; 32]> {
+        (0..n).map(|_| self.next_key()).collect()
+    }
+
+    pub fn kvs(&mut self, n: usize) -> Vec<KeyValue> {
+        self.take(n).collect()
+    }
+}
+
+impl Iterator for Workload {
+    type Item = KeyValue;
+
+    fn next(&mut self) -> Option<KeyValue> {
+        let key = self.next_key();
+        Some((key, self.next_value()))
+    }
+}
+
+// Stand-in for the Pedersen tree key, which is too slow for large workloads.
+// It keeps the layout: one stem per (address, tree index) pair.
+fn hashed_stem(address: &[u8; 20], tree_index: &[u8; 32]) -> [u8; 31] {
+    let mut hasher = Keccak256::new();
+    hasher.update(address);
+    hasher.update(tree_index);
+    hasher.finalize()[0..31].try_into().unwrap()
+}
+
+fn with_suffix(stem: &[u8; 31], suffix: u8) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    key[0..31].copy_from_slice(stem);
+    key[31] = suffix;
+    key
+}
+
+// The header leaves and the first `slots` storage slots of an account
+fn account_keys(address: &[u8; 20], slots: u64) -> Vec<[u8; 32]> {
+    let header_stem = hashed_stem(address, &[0u8; 32]);
+    let mut keys: Vec<_> = (BASIC_DATA_LEAF_KEY..=CODE_HASH_LEAF_KEY)
+        .map(|leaf| with_suffix(&header_stem, leaf))
+        .collect();
+    for slot in 0..slots {
+        let mut slot_bytes = [0u8; 32];
+        slot_bytes[24..].copy_from_slice(&slot.to_be_bytes());
+        let (tree_index, sub_index) = storage_slot_position(&slot_bytes);
+        keys.push(with_suffix(&hashed_stem(address, &tree_index), sub_index));
+    }
+    keys
+}
+
+pub fn write_fixture(
+    path: impl AsRef<Path>,
+    seed: u64,
+    distribution: Distribution,
+    n: usize,
+) -> io::Result<()> {
+    let kvs = Workload::new(seed, distribution).kvs(n);
+    write_csv(File::create(path)?, &kvs)
+}
+
+pub fn workloads_are_reproducible() {
+    let distributions = [
+        Distribution::Uniform,
+        Distribution::ClusteredStems { stems: 8 },
+        Distribution::AccountLike { slots: 100 },
+        Distribution::SharedPrefix { prefix_len: 30 },
+        Distribution::MixedPrefixes,
+    ];
+
+    for distribution in distributions {
+        let first = Workload::new(7, distribution).kvs(1_000);
+        assert_eq!(first, Workload::new(7, distribution).kvs(1_000));
+        assert_ne!(first, Workload::new(8, distribution).kvs(1_000));
+
+        let mut stems: Vec<&[u8]> = first.iter().map(|(key, _)| &key[0..31]).collect();
+        stems.sort();
+        stems.dedup();
+        println!(
+            "{:?}: {} distinct stems in 1000 keys",
+            distribution,
+            stems.len()
+        );
+
+        match distribution {
+            Distribution::ClusteredStems { stems: n } => assert!(stems.len() <= n),
+            Distribution::AccountLike { slots } => {
+                // The header leaves and first 64 slots share a stem, the rest of the slots do not
+                let header_leaves = (CODE_HASH_LEAF_KEY - BASIC_DATA_LEAF_KEY + 1) as u64;
+                let in_header = (header_leaves + CODE_OFFSET - HEADER_STORAGE_OFFSET) as usize;
+                let per_account = (header_leaves + slots) as usize;
+                assert_eq!(first[0].0[0..31], first[in_header - 1].0[0..31]);
+                assert_ne!(first[0].0[0..31], first[in_header].0[0..31]);
+                assert_ne!(first[0].0[0..31], first[per_account].0[0..31]);
+            }
+            Distribution::SharedPrefix { prefix_len } => {
+                assert!(first
+                    .iter()
+                    .all(|(key, _)| key[0..prefix_len] == first[0].0[0..prefix_len]));
+            }
+            Distribution::Uniform => assert_eq!(stems.len(), first.len()),
+            Distribution::MixedPrefixes => assert!(stems.len() < first.len()),
+        }
+    }
+
+    let path = std::env::temp_dir().join("workload_fixture.csv");
+    write_fixture(&path, 7, Distribution::AccountLike { slots: 10 }, 100).unwrap();
+    let file = BufReader::new(File::open(&path).unwrap());
+    let read: Vec<KeyValue> = read_csv(file).collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        read,
+        Workload::new(7, Distribution::AccountLike { slots: 10 }).kvs(100)
+    );
+    std::fs::remove_file(&path).unwrap();
+}